@@ -1,36 +1,256 @@
+use std::cell::OnceCell;
 use std::error::Error;
 use std::result::Result;
-use std::time::{SystemTime, UNIX_EPOCH};
 
+use coremark_engine::{Capabilities, Engine, Instance, Module, UnexpectedResult, Value};
 use wamr_rust_sdk::{
-    function::Function, generate_host_function, instance::Instance, module::Module,
-    runtime::RuntimeBuilder, value::WasmValue,
+    function::Function, generate_host_function, runtime::Runtime, runtime::RuntimeBuilder,
+    value::WasmValue,
 };
 
 #[generate_host_function]
 fn clock_ms() -> i64 {
-    SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .expect("Clock may have gone backwards")
-        .as_millis() as i64
+    coremark_engine::clock_ms()
 }
 
-pub fn wamr_container(b: &[u8]) -> Result<f32, Box<dyn Error>> {
-    let runtime = RuntimeBuilder::new("env")
-        .use_system_allocator()
-        .run_as_interpreter()
-        .register_host_function(clock_ms)
-        .build()?;
+#[generate_host_function]
+fn clock_ns() -> i64 {
+    coremark_engine::clock_ns()
+}
+
+/// Stack of each instance. The JIT modes need more than the interpreter's 2 KiB.
+const STACK_SIZE: u32 = 64 * 1024;
+
+thread_local! {
+    // WAMR keeps one runtime per process, so the engines of every running mode share it. The
+    // harness only drives WAMR from the main thread.
+    static RUNTIME: OnceCell<&'static Runtime> = const { OnceCell::new() };
+}
+
+fn runtime() -> Result<&'static Runtime, Box<dyn Error>> {
+    RUNTIME.with(|cell| {
+        if let Some(runtime) = cell.get() {
+            return Ok(*runtime);
+        }
+
+        let runtime = RuntimeBuilder::new("env")
+            .use_system_allocator()
+            .run_as_interpreter()
+            .register_host_function(clock_ms)
+            .register_host_function(clock_ns)
+            .build()?;
+
+        Ok(*cell.get_or_init(|| Box::leak(Box::new(runtime))))
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RunningMode {
+    Interpreter,
+    FastJit,
+    LlvmJit,
+    /// An AOT image built by `wamrc`, which has to be on the `PATH`.
+    #[cfg(feature = "aot")]
+    Aot,
+}
+
+impl RunningMode {
+    pub fn name(self) -> &'static str {
+        match self {
+            RunningMode::Interpreter => "wamr",
+            RunningMode::FastJit => "wamr-fast-jit",
+            RunningMode::LlvmJit => "wamr-llvm-jit",
+            #[cfg(feature = "aot")]
+            RunningMode::Aot => "wamr-aot",
+        }
+    }
+
+    // AOT images run as native code whatever the mode, so they load under the interpreter's.
+    fn raw(self) -> wamr_sys::RunningMode {
+        match self {
+            RunningMode::Interpreter => wamr_sys::RunningMode_Mode_Interp,
+            RunningMode::FastJit => wamr_sys::RunningMode_Mode_Fast_JIT,
+            RunningMode::LlvmJit => wamr_sys::RunningMode_Mode_LLVM_JIT,
+            #[cfg(feature = "aot")]
+            RunningMode::Aot => wamr_sys::RunningMode_Mode_Interp,
+        }
+    }
+
+    /// Whether the linked WAMR library was built with this mode.
+    pub fn is_supported(self) -> bool {
+        unsafe { wamr_sys::wasm_runtime_is_running_mode_supported(self.raw()) }
+    }
+
+    // The mode applies to modules loaded and instantiated after it is set.
+    fn activate(self) -> Result<(), Box<dyn Error>> {
+        if !unsafe { wamr_sys::wasm_runtime_set_default_running_mode(self.raw()) } {
+            return Err(format!("failed to switch WAMR to {}", self.name()).into());
+        }
+        Ok(())
+    }
+}
+
+pub struct WamrEngine {
+    mode: RunningMode,
+}
+
+impl WamrEngine {
+    pub fn new() -> Self {
+        Self {
+            mode: RunningMode::Interpreter,
+        }
+    }
+
+    /// Fails up front when the linked WAMR library was built without `mode`. For AOT, also when
+    /// `wamrc` is missing or the library cannot load the images it builds.
+    pub fn with_mode(mode: RunningMode) -> Result<Self, Box<dyn Error>> {
+        #[cfg(feature = "aot")]
+        if mode == RunningMode::Aot {
+            probe_aot()?;
+            return Ok(Self { mode });
+        }
+        if !mode.is_supported() {
+            return Err("the linked WAMR library does not support this running mode".into());
+        }
+        Ok(Self { mode })
+    }
+}
+
+impl Default for WamrEngine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Engine for WamrEngine {
+    fn name(&self) -> &str {
+        self.mode.name()
+    }
 
-    let module = Module::from_vec(&runtime, Vec::from(&b[..]), "coremark")?;
+    fn version(&self) -> &str {
+        "git"
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            interruptible: false,
+            shared_module: false,
+        }
+    }
+
+    #[cfg(feature = "aot")]
+    fn precompile<'w>(&self, wasm: &'w [u8]) -> Result<std::borrow::Cow<'w, [u8]>, Box<dyn Error>> {
+        if self.mode != RunningMode::Aot {
+            return Ok(wasm.into());
+        }
+        Ok(wamrc(wasm)?.into())
+    }
+
+    // The runtime tells AOT images from bytecode by their header, so both load the same way.
+    fn compile<'e>(&'e self, wasm: &[u8]) -> Result<Box<dyn Module + 'e>, Box<dyn Error>> {
+        let runtime = runtime()?;
+        self.mode.activate()?;
+        let module = wamr_rust_sdk::module::Module::from_vec(runtime, wasm.to_vec(), "coremark")?;
+
+        Ok(Box::new(WamrModule {
+            runtime,
+            mode: self.mode,
+            module,
+        }))
+    }
+}
+
+/// Smallest valid module, which goes through `wamrc` and the AOT loader to probe both.
+#[cfg(feature = "aot")]
+const EMPTY_MODULE: &[u8] = b"\0asm\x01\0\0\0";
+
+#[cfg(feature = "aot")]
+fn probe_aot() -> Result<(), Box<dyn Error>> {
+    let version = std::process::Command::new("wamrc")
+        .arg("--version")
+        .output()
+        .map_err(|e| format!("wamrc is not on the PATH: {}", e))?;
+    if !version.status.success() {
+        return Err(format!("wamrc --version failed: {}", String::from_utf8_lossy(&version.stderr).trim()).into());
+    }
+
+    let image = wamrc(EMPTY_MODULE)?;
+    RunningMode::Aot.activate()?;
+    wamr_rust_sdk::module::Module::from_vec(runtime()?, image, "probe")
+        .map_err(|e| format!("the linked WAMR library cannot load AOT images: {}", e))?;
+    Ok(())
+}
+
+#[cfg(feature = "aot")]
+static NEXT_IMAGE: std::sync::atomic::AtomicU32 = std::sync::atomic::AtomicU32::new(0);
+
+#[cfg(feature = "aot")]
+fn wamrc(wasm: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+    use std::fs;
+    use std::process::{self, Command};
+    use std::sync::atomic::Ordering;
 
-    let instance = Instance::new(&runtime, &module, 2 * 1024)?;
+    let id = NEXT_IMAGE.fetch_add(1, Ordering::Relaxed);
+    let input = std::env::temp_dir().join(format!("coremark-wamr-{}-{}.wasm", process::id(), id));
+    let output = input.with_extension("aot");
+    fs::write(&input, wasm)?;
 
-    let function = Function::find_export_func(&instance, "run")?;
+    let compiled = Command::new("wamrc").arg("-o").arg(&output).arg(&input).output();
+    let _ = fs::remove_file(&input);
+    let compiled = compiled.map_err(|e| format!("failed to run wamrc: {}", e))?;
+    let image = fs::read(&output);
+    let _ = fs::remove_file(&output);
+
+    if !compiled.status.success() {
+        return Err(format!("wamrc failed: {}", String::from_utf8_lossy(&compiled.stderr).trim()).into());
+    }
+    Ok(image?)
+}
+
+struct WamrModule {
+    runtime: &'static Runtime,
+    mode: RunningMode,
+    module: wamr_rust_sdk::module::Module<'static>,
+}
+
+impl Module for WamrModule {
+    fn instantiate(&mut self) -> Result<Box<dyn Instance + '_>, Box<dyn Error>> {
+        self.mode.activate()?;
+        let instance = wamr_rust_sdk::instance::Instance::new(self.runtime, &self.module, STACK_SIZE)?;
+
+        Ok(Box::new(WamrInstance { instance }))
+    }
+}
+
+struct WamrInstance<'m> {
+    instance: wamr_rust_sdk::instance::Instance<'m>,
+}
+
+impl Instance for WamrInstance<'_> {
+    fn invoke(&mut self, export: &str, args: &[Value]) -> Result<Value, Box<dyn Error>> {
+        let function = Function::find_export_func(&self.instance, export)?;
+
+        let params: Vec<WasmValue> = args.iter().map(|&arg| to_wasm_value(arg)).collect();
+
+        match function.call(&self.instance, &params)? {
+            WasmValue::I32(v) => Ok(Value::I32(v)),
+            WasmValue::I64(v) => Ok(Value::I64(v)),
+            WasmValue::F32(v) => Ok(Value::F32(v)),
+            WasmValue::F64(v) => Ok(Value::F64(v)),
+            _ => Err(UnexpectedResult {
+                export: export.to_string(),
+                value: None,
+            }
+            .into()),
+        }
+    }
+}
 
-    if let WasmValue::F32(res) = function.call(&instance, &vec![])? {
-        Ok(res)
-    } else {
-        panic!("Failed running coremark in wamr");
+fn to_wasm_value(value: Value) -> WasmValue {
+    match value {
+        Value::I32(v) => WasmValue::I32(v),
+        Value::I64(v) => WasmValue::I64(v),
+        Value::F32(v) => WasmValue::F32(v),
+        Value::F64(v) => WasmValue::F64(v),
     }
 }
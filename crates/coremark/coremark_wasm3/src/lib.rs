@@ -1,26 +1,103 @@
 use std::error::Error;
 use std::result::Result;
-use std::time::{SystemTime, UNIX_EPOCH};
 
-use wasm3::{Environment, Module};
+use coremark_engine::{clock_ms, clock_ns, Capabilities, Engine, Instance, Module, Value};
+use wasm3::{Environment, ParsedModule, Runtime};
 
-pub fn clock_ms() -> i64 {
-    SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .expect("Clock may have gone backwards")
-        .as_millis() as i64
+pub struct Wasm3Engine;
+
+impl Wasm3Engine {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl Default for Wasm3Engine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Engine for Wasm3Engine {
+    fn name(&self) -> &str {
+        "wasm3"
+    }
+
+    fn version(&self) -> &str {
+        "git"
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            interruptible: false,
+            shared_module: false,
+        }
+    }
+
+    fn compile<'e>(&'e self, wasm: &[u8]) -> Result<Box<dyn Module + 'e>, Box<dyn Error>> {
+        let env = Environment::new()?;
+        let parsed = wasm3::Module::parse(&env, wasm)?;
+
+        Ok(Box::new(Wasm3Module {
+            env,
+            wasm: wasm.to_vec(),
+            parsed: Some(parsed),
+        }))
+    }
+}
+
+struct Wasm3Module {
+    env: Environment,
+    wasm: Vec<u8>,
+    parsed: Option<ParsedModule>,
+}
+
+impl Module for Wasm3Module {
+    fn instantiate(&mut self) -> Result<Box<dyn Instance + '_>, Box<dyn Error>> {
+        // Loading hands the parsed module over to the runtime, so later instances parse it again.
+        let parsed = match self.parsed.take() {
+            Some(parsed) => parsed,
+            None => wasm3::Module::parse(&self.env, &self.wasm[..])?,
+        };
+
+        let rt = self.env.create_runtime(2 * 1024)?;
+        let mut module = rt.load_module(parsed)?;
+        // wasm3 refuses to link functions the module does not import, and a guest imports only
+        // one of the clocks, so a failed link is left for the call to report.
+        let _ = module.link_function::<(), i64>("env", "clock_ms", clock_ms_wrap);
+        let _ = module.link_function::<(), i64>("env", "clock_ns", clock_ns_wrap);
+
+        Ok(Box::new(Wasm3Instance { rt }))
+    }
 }
 
-pub fn wasm3_container(b: &[u8]) -> Result<f32, Box<dyn Error>> {
-    let env = Environment::new()?;
-    let rt = env.create_runtime(2 * 1024)?;
-    let mut module = rt.load_module(Module::parse(&env, &b[..])?)?;
+struct Wasm3Instance {
+    rt: Runtime,
+}
 
-    module.link_function::<(), i64>("env", "clock_ms", clock_ms_wrap)?;
+impl Instance for Wasm3Instance {
+    fn invoke(&mut self, export: &str, args: &[Value]) -> Result<Value, Box<dyn Error>> {
+        // wasm3 only exposes statically typed calls, so each export signature the guest uses is listed here.
+        match args {
+            [] => match self.rt.find_function::<(), f32>(export) {
+                Ok(func) => Ok(Value::F32(func.call()?)),
+                Err(_) => Ok(Value::I32(self.rt.find_function::<(), i32>(export)?.call()?)),
+            },
+            [Value::I32(a), Value::I32(b), Value::I32(c), Value::I32(d)] => Ok(Value::F32(
+                self.rt
+                    .find_function::<(i32, i32, i32, i32), f32>(export)?
+                    .call(*a, *b, *c, *d)?,
+            )),
+            _ => Err(format!("unsupported signature for `{}` in wasm3", export).into()),
+        }
+    }
 
-    Ok(module
-        .find_function::<(), f32>("run")?
-        .call()?)
+    // wasm3 only exposes the memory of the runtime, which holds the single loaded module.
+    fn memory(&mut self, _export: &str) -> Option<Vec<u8>> {
+        let memory = unsafe { &*self.rt.memory() };
+        Some(memory.to_vec())
+    }
 }
 
 wasm3::make_func_wrapper!(clock_ms_wrap: clock_ms() -> i64);
+wasm3::make_func_wrapper!(clock_ns_wrap: clock_ns() -> i64);
@@ -1,41 +1,243 @@
 use std::collections::HashMap;
 use std::error::Error;
+use std::fs;
+use std::path::PathBuf;
+use std::process;
 use std::result::Result;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::Instant;
 
+use coremark_engine::{Capabilities, Engine, Instance, Module, UnexpectedResult, Value};
+pub use wasmedge_sdk::CompilerOptimizationLevel;
 use wasmedge_sdk::{
-    error::CoreError, params, AsInstance, CallingFrame, ImportObjectBuilder, Instance, Module,
-    Store, Vm, WasmValue,
+    error::CoreError, AsInstance, CallingFrame, ImportObject, ImportObjectBuilder, Store,
+    SyncInst, ValType, Vm, WasmValue,
 };
 
 fn clock_ms(
     _: &mut (),
-    _inst: &mut Instance,
+    _inst: &mut wasmedge_sdk::Instance,
     _frame: &mut CallingFrame,
     _input: Vec<WasmValue>,
 ) -> Result<Vec<WasmValue>, CoreError> {
-    let now = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .expect("Clock may have gone backwards")
-        .as_millis() as i64;
+    Ok(vec![WasmValue::from_i64(coremark_engine::clock_ms())])
+}
+
+fn clock_ns(
+    _: &mut (),
+    _inst: &mut wasmedge_sdk::Instance,
+    _frame: &mut CallingFrame,
+    _input: Vec<WasmValue>,
+) -> Result<Vec<WasmValue>, CoreError> {
+    Ok(vec![WasmValue::from_i64(coremark_engine::clock_ns())])
+}
+
+pub struct WasmedgeEngine {
+    name: &'static str,
+    aot: Option<CompilerOptimizationLevel>,
+}
+
+impl WasmedgeEngine {
+    pub fn new() -> Self {
+        Self {
+            name: "wasmedge",
+            aot: None,
+        }
+    }
+
+    /// Compiles the module to a native shared library with the WasmEdge AOT compiler before it is
+    /// loaded. O3 is the compiler's default and keeps the plain `wasmedge-aot` name.
+    #[cfg(feature = "aot")]
+    pub fn aot(mut self, level: CompilerOptimizationLevel) -> Self {
+        self.name = match level {
+            CompilerOptimizationLevel::O0 => "wasmedge-aot-o0",
+            CompilerOptimizationLevel::O1 => "wasmedge-aot-o1",
+            CompilerOptimizationLevel::O2 => "wasmedge-aot-o2",
+            CompilerOptimizationLevel::O3 => "wasmedge-aot",
+            CompilerOptimizationLevel::Os => "wasmedge-aot-os",
+            CompilerOptimizationLevel::Oz => "wasmedge-aot-oz",
+        };
+        self.aot = Some(level);
+        self
+    }
+}
 
-    Ok(vec![WasmValue::from_i64(now)])
+impl Default for WasmedgeEngine {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
-pub fn wasmedge_container(b: &[u8]) -> Result<f32, Box<dyn Error>> {
-    let mut import_builder = ImportObjectBuilder::new("env", ())?;
-    import_builder.with_func::<(), i64>("clock_ms", clock_ms)?;
-    let mut import_object = import_builder.build();
+impl Engine for WasmedgeEngine {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn version(&self) -> &str {
+        "0.14"
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        // Calls with a timeout are only available on Linux.
+        Capabilities {
+            interruptible: cfg!(target_os = "linux"),
+            shared_module: false,
+        }
+    }
+
+    #[cfg(feature = "aot")]
+    fn precompile<'w>(&self, wasm: &'w [u8]) -> Result<std::borrow::Cow<'w, [u8]>, Box<dyn Error>> {
+        use wasmedge_sdk::config::{CommonConfigOptions, CompilerConfigOptions, ConfigBuilder};
+        use wasmedge_sdk::CompilerOutputFormat;
+
+        let Some(level) = self.aot else {
+            return Ok(wasm.into());
+        };
+
+        let options = CompilerConfigOptions::default()
+            .optimization_level(level)
+            .out_format(CompilerOutputFormat::Native);
+        let config = ConfigBuilder::new(CommonConfigOptions::default())
+            .with_compiler_config(options)
+            .build()?;
+        let compiler = wasmedge_sdk::Compiler::new(Some(&config))?;
+        let artifact = Artifact(compiler.compile_from_bytes(wasm, Artifact::name(), std::env::temp_dir())?);
+
+        Ok(fs::read(&artifact.0)?.into())
+    }
+
+    fn compile<'e>(&'e self, wasm: &[u8]) -> Result<Box<dyn Module + 'e>, Box<dyn Error>> {
+        // Shared libraries are loaded from a file, so the artifact goes back to disk first.
+        let artifact = match self.aot {
+            Some(_) => Some(Artifact::write(wasm)?),
+            None => None,
+        };
+        let module = load(wasm, artifact.as_ref())?;
+
+        let mut import_builder = ImportObjectBuilder::new("env", ())?;
+        import_builder.with_func::<(), i64>("clock_ms", clock_ms)?;
+        import_builder.with_func::<(), i64>("clock_ns", clock_ns)?;
+        let import_object = import_builder.build();
+
+        Ok(Box::new(WasmedgeModule {
+            wasm: wasm.to_vec(),
+            artifact,
+            module: Some(module),
+            import_object,
+        }))
+    }
+}
 
-    let mut instances = HashMap::new();
-    instances.insert(import_object.name().unwrap(), &mut import_object);
+static NEXT_ARTIFACT: AtomicU32 = AtomicU32::new(0);
 
-    let module = Module::from_bytes(None, &b[..])?;
+/// Shared library built by the AOT compiler, removed when dropped.
+struct Artifact(PathBuf);
 
-    let mut vm = Vm::new(Store::new(None, instances).unwrap());
+impl Artifact {
+    fn name() -> String {
+        format!("coremark-wasmedge-{}-{}", process::id(), NEXT_ARTIFACT.fetch_add(1, Ordering::Relaxed))
+    }
 
-    vm.register_module(None, module)?;
-    let result = vm.run_func(None, "run", params!())?;
+    fn write(data: &[u8]) -> Result<Self, Box<dyn Error>> {
+        let path = std::env::temp_dir().join(format!("{}.{}", Self::name(), std::env::consts::DLL_EXTENSION));
+        fs::write(&path, data)?;
+        Ok(Self(path))
+    }
+}
+
+impl Drop for Artifact {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+fn load(wasm: &[u8], artifact: Option<&Artifact>) -> Result<wasmedge_sdk::Module, Box<dyn Error>> {
+    Ok(match artifact {
+        Some(artifact) => wasmedge_sdk::Module::from_file(None, &artifact.0)?,
+        None => wasmedge_sdk::Module::from_bytes(None, wasm)?,
+    })
+}
+
+struct WasmedgeModule {
+    wasm: Vec<u8>,
+    artifact: Option<Artifact>,
+    module: Option<wasmedge_sdk::Module>,
+    import_object: ImportObject<()>,
+}
+
+impl Module for WasmedgeModule {
+    fn instantiate(&mut self) -> Result<Box<dyn Instance + '_>, Box<dyn Error>> {
+        // Registering consumes the loaded module, so later instances load it again.
+        let module = match self.module.take() {
+            Some(module) => module,
+            None => load(&self.wasm, self.artifact.as_ref())?,
+        };
+
+        let mut instances: HashMap<String, &mut dyn SyncInst> = HashMap::new();
+        instances.insert(self.import_object.name().unwrap(), &mut self.import_object);
+
+        let mut vm = Vm::new(Store::new(None, instances)?);
+        vm.register_module(None, module)?;
+
+        Ok(Box::new(WasmedgeInstance { vm, deadline: None }))
+    }
+}
+
+struct WasmedgeInstance<'m> {
+    vm: Vm<'m, dyn SyncInst>,
+    deadline: Option<Instant>,
+}
+
+impl Instance for WasmedgeInstance<'_> {
+    fn invoke(&mut self, export: &str, args: &[Value]) -> Result<Value, Box<dyn Error>> {
+        let params: Vec<WasmValue> = args.iter().map(|&arg| to_wasm_value(arg)).collect();
+        let results = match self.deadline {
+            #[cfg(target_os = "linux")]
+            Some(deadline) => {
+                let timeout = deadline.saturating_duration_since(Instant::now());
+                self.vm.run_func_with_timeout(None, export, params, timeout)?
+            }
+            _ => self.vm.run_func(None, export, params)?,
+        };
+
+        results
+            .first()
+            .and_then(from_wasm_value)
+            .ok_or_else(|| {
+                UnexpectedResult {
+                    export: export.to_string(),
+                    value: None,
+                }
+                .into()
+            })
+    }
+
+    fn set_deadline(&mut self, deadline: Option<Instant>) -> bool {
+        self.deadline = deadline;
+        cfg!(target_os = "linux")
+    }
+
+    fn memory(&mut self, export: &str) -> Option<Vec<u8>> {
+        let memory = self.vm.active_module()?.get_memory_ref(export).ok()?;
+        memory.get_data(0, memory.size() * 65536).ok()
+    }
+}
+
+fn to_wasm_value(value: Value) -> WasmValue {
+    match value {
+        Value::I32(v) => WasmValue::from_i32(v),
+        Value::I64(v) => WasmValue::from_i64(v),
+        Value::F32(v) => WasmValue::from_f32(v),
+        Value::F64(v) => WasmValue::from_f64(v),
+    }
+}
 
-    Ok(result[0].to_f32())
+fn from_wasm_value(value: &WasmValue) -> Option<Value> {
+    match value.ty() {
+        ValType::I32 => Some(Value::I32(value.to_i32())),
+        ValType::I64 => Some(Value::I64(value.to_i64())),
+        ValType::F32 => Some(Value::F32(value.to_f32())),
+        ValType::F64 => Some(Value::F64(value.to_f64())),
+        _ => None,
+    }
 }
@@ -1,14 +1,38 @@
-use coremark_wasmedge::wasmedge_container;
+use std::env;
+use std::fs;
+
+use coremark_engine::{Engine, Value};
+use coremark_wasmedge::WasmedgeEngine;
 
 fn main() {
     let coremark_wasm = include_bytes!("../../../target/wasm32-unknown-unknown/release/coremark_wasm.wasm");
 
+    let wasm = match env::args().nth(1) {
+        Some(path) => match fs::read(&path) {
+            Ok(wasm) => wasm,
+            Err(e) => {
+                eprintln!("Error occurred: failed to read {}: {}", path, e);
+                return;
+            }
+        },
+        None => coremark_wasm.to_vec(),
+    };
+
     println!("Running Coremark test on wasmedge");
 
-    match wasmedge_container(coremark_wasm) {
-        Ok(value) => {
+    let engine = WasmedgeEngine::new();
+    let result = engine.compile(&wasm).and_then(|mut module| {
+        let mut instance = module.instantiate()?;
+        instance.invoke("run", &[Value::I32(100_000), Value::I32(0), Value::I32(0), Value::I32(0x66)])
+    });
+
+    match result {
+        Ok(Value::F32(value)) => {
             println!("wasmedge: {}", value);
         },
+        Ok(value) => {
+            eprintln!("Error occurred: unexpected result {}", value);
+        },
         Err(e) => {
             eprintln!("Error occurred: {}", e);
         }
@@ -1,34 +1,187 @@
+use std::borrow::Cow;
 use std::error::Error;
 use std::result::Result;
-use std::time::{SystemTime, UNIX_EPOCH};
 
-use wasmer::{
-    imports, Function, FunctionType, Instance, Module, Store, Type, TypedFunction, Value,
+use coremark_engine::{
+    clock_ms, clock_ns, Capabilities, Engine, Instance, Module, SharedModule, UnexpectedResult,
+    Value,
 };
+use wasmer::{imports, Function, Store};
 
-pub fn wasmer_container(b: &[u8]) -> Result<f32, Box<dyn Error>> {
-    let mut store = Store::default();
-    let module = Module::new(&store, &b[..])?;
+/// Compiler turning the module into native code, each behind the cargo feature of the same name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compiler {
+    #[cfg(feature = "cranelift")]
+    Cranelift,
+    #[cfg(feature = "singlepass")]
+    Singlepass,
+    #[cfg(feature = "llvm")]
+    Llvm,
+}
+
+pub struct WasmerEngine {
+    name: String,
+    engine: wasmer::Engine,
+    aot: bool,
+}
+
+impl WasmerEngine {
+    #[cfg(feature = "cranelift")]
+    pub fn new() -> Self {
+        Self::with_compiler(Compiler::Cranelift)
+    }
+
+    /// Cranelift is wasmer's default compiler and keeps the plain `wasmer` name.
+    pub fn with_compiler(compiler: Compiler) -> Self {
+        // The engine is built once here, so setting up the compiler is not timed as compilation.
+        let (name, engine): (&str, wasmer::Engine) = match compiler {
+            #[cfg(feature = "cranelift")]
+            Compiler::Cranelift => ("wasmer", wasmer::Cranelift::default().into()),
+            #[cfg(feature = "singlepass")]
+            Compiler::Singlepass => ("wasmer-singlepass", wasmer::Singlepass::default().into()),
+            #[cfg(feature = "llvm")]
+            Compiler::Llvm => ("wasmer-llvm", wasmer::LLVM::default().into()),
+        };
+
+        Self {
+            name: name.to_string(),
+            engine,
+            aot: false,
+        }
+    }
+
+    /// Compiles a `wasmer::Module` up front and serialises it, so `compile` only loads the artifact into
+    /// a clone of the engine.
+    #[cfg(feature = "aot")]
+    pub fn aot(mut self) -> Self {
+        self.name.push_str("-aot");
+        self.aot = true;
+        self
+    }
+}
 
-    let clock_ms_host_signature = FunctionType::new(vec![], vec![Type::I64]);
-    let clock_ms_host = Function::new(&mut store, &clock_ms_host_signature, |_| {
-        let now = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .expect("Clock may have gone backwards")
-            .as_millis() as i64;
+#[cfg(feature = "cranelift")]
+impl Default for WasmerEngine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Engine for WasmerEngine {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn version(&self) -> &str {
+        "5.0"
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            interruptible: false,
+            shared_module: true,
+        }
+    }
 
-        Ok(vec![Value::I64(now)])
-    });
+    fn precompile<'w>(&self, wasm: &'w [u8]) -> Result<Cow<'w, [u8]>, Box<dyn Error>> {
+        if !self.aot {
+            return Ok(Cow::Borrowed(wasm));
+        }
+        let module = wasmer::Module::new(&self.engine, wasm)?;
+        Ok(Cow::Owned(module.serialize()?.to_vec()))
+    }
 
-    let import_object = imports! {
-        "env" => {
-            "clock_ms" => clock_ms_host,
-        },
-    };
+    fn compile<'e>(&'e self, wasm: &[u8]) -> Result<Box<dyn Module + 'e>, Box<dyn Error>> {
+        let engine = self.engine.clone();
+        let module = if self.aot {
+            // Safety: `precompile` serialised a module that a clone of this engine compiled.
+            unsafe { wasmer::Module::deserialize(&engine, wasm)? }
+        } else {
+            wasmer::Module::new(&engine, wasm)?
+        };
+
+        Ok(Box::new(WasmerModule { engine, module }))
+    }
+}
+
+struct WasmerModule {
+    engine: wasmer::Engine,
+    module: wasmer::Module,
+}
 
-    let instance = Instance::new(&mut store, &module, &import_object)?;
+impl Module for WasmerModule {
+    fn instantiate(&mut self) -> Result<Box<dyn Instance + '_>, Box<dyn Error>> {
+        self.instantiate_shared()
+    }
 
-    let run: TypedFunction<(), f32> = instance.exports.get_function("run")?.typed(&mut store)?;
+    fn shared(&self) -> Option<&dyn SharedModule> {
+        Some(self)
+    }
+}
+
+impl SharedModule for WasmerModule {
+    // Stores are not thread safe, so every instance gets its own store of the compiling engine.
+    fn instantiate_shared(&self) -> Result<Box<dyn Instance + '_>, Box<dyn Error>> {
+        let mut store = Store::new(self.engine.clone());
+
+        let import_object = imports! {
+            "env" => {
+                "clock_ms" => Function::new_typed(&mut store, clock_ms),
+                "clock_ns" => Function::new_typed(&mut store, clock_ns),
+            },
+        };
+
+        let instance = wasmer::Instance::new(&mut store, &self.module, &import_object)?;
+
+        Ok(Box::new(WasmerInstance { store, instance }))
+    }
+}
+
+struct WasmerInstance {
+    store: Store,
+    instance: wasmer::Instance,
+}
+
+impl Instance for WasmerInstance {
+    fn invoke(&mut self, export: &str, args: &[Value]) -> Result<Value, Box<dyn Error>> {
+        let func = self.instance.exports.get_function(export)?;
+
+        let params: Vec<wasmer::Value> = args.iter().map(|&arg| to_value(arg)).collect();
+        let results = func.call(&mut self.store, &params)?;
+
+        results
+            .first()
+            .and_then(from_value)
+            .ok_or_else(|| {
+                UnexpectedResult {
+                    export: export.to_string(),
+                    value: None,
+                }
+                .into()
+            })
+    }
+
+    fn memory(&mut self, export: &str) -> Option<Vec<u8>> {
+        let memory = self.instance.exports.get_memory(export).ok()?;
+        memory.view(&self.store).copy_to_vec().ok()
+    }
+}
+
+fn to_value(value: Value) -> wasmer::Value {
+    match value {
+        Value::I32(v) => wasmer::Value::I32(v),
+        Value::I64(v) => wasmer::Value::I64(v),
+        Value::F32(v) => wasmer::Value::F32(v),
+        Value::F64(v) => wasmer::Value::F64(v),
+    }
+}
 
-    Ok(run.call(&mut store)?)
+fn from_value(value: &wasmer::Value) -> Option<Value> {
+    match *value {
+        wasmer::Value::I32(v) => Some(Value::I32(v)),
+        wasmer::Value::I64(v) => Some(Value::I64(v)),
+        wasmer::Value::F32(v) => Some(Value::F32(v)),
+        wasmer::Value::F64(v) => Some(Value::F64(v)),
+        _ => None,
+    }
 }
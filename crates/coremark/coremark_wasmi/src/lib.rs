@@ -1,29 +1,231 @@
 use std::error::Error;
 use std::result::Result;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use wasmi::{Engine, Func, Linker, Module, Store};
+use coremark_engine::{
+    clock_ms, clock_ns, Capabilities, Engine, Instance, Module, SharedModule, UnexpectedResult,
+    Value,
+};
+use wasmi::{Config, Func, Linker, Store, Val};
 
-pub fn wasmi_container(b: &[u8]) -> Result<f32, Box<dyn Error>> {
-    let engine = Engine::default();
+pub use wasmi::CompilationMode;
 
-    let module = Module::new(&engine, &b[..])?;
+/// Fuel a call gets per second until the deadline before any rate was measured, above what wasmi
+/// burns, so the guest never runs out early.
+const MAX_FUEL_PER_SECOND: f64 = 4e9;
 
-    let mut store = Store::new(&engine, 64);
-    let func = Func::wrap(&mut store, || {
-        SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .expect("Clock may have gone backwards")
-            .as_millis() as i64
-    });
+/// Shortest call whose fuel rate is trusted, shorter ones are dominated by call overhead.
+const MIN_RATE_SAMPLE: Duration = Duration::from_millis(10);
 
-    let mut linker = <Linker<i64>>::new(&engine);
-    linker.define("env", "clock_ms", func)?;
-    let instance = linker
-        .instantiate(&mut store, &module)?
-        .start(&mut store)?;
+pub struct WasmiEngine {
+    name: String,
+    engine: wasmi::Engine,
+    fuel: bool,
+    /// Highest rate at which calls burnt fuel, as `f64` bits so that the maximum of the bits is the
+    /// maximum rate. Shared by every instance, so later repetitions start from it.
+    fuel_per_second: Arc<AtomicU64>,
+}
+
+impl WasmiEngine {
+    pub fn new() -> Self {
+        Self::with_mode(CompilationMode::Eager, false, false)
+    }
+
+    /// With `fuel` every instance meters the instructions it executes, starting with enough fuel
+    /// to never run out, so only the cost of metering is measured. `fuel_interruption` meters them
+    /// too, so that calls under a deadline only get the fuel they burn until then.
+    pub fn with_mode(mode: CompilationMode, fuel: bool, fuel_interruption: bool) -> Self {
+        let mut config = Config::default();
+        config.compilation_mode(mode).consume_fuel(fuel || fuel_interruption);
+
+        let mut name = match mode {
+            CompilationMode::Eager => "wasmi",
+            CompilationMode::LazyTranslation => "wasmi-lazy-translation",
+            CompilationMode::Lazy => "wasmi-lazy",
+        }
+        .to_string();
+        if fuel {
+            name.push_str("-fuel");
+        }
+
+        Self {
+            name,
+            engine: wasmi::Engine::new(&config),
+            fuel: fuel || fuel_interruption,
+            fuel_per_second: Arc::default(),
+        }
+    }
+}
+
+impl Default for WasmiEngine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Engine for WasmiEngine {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn version(&self) -> &str {
+        "0.40"
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            interruptible: self.fuel,
+            shared_module: true,
+        }
+    }
+
+    fn compile<'e>(&'e self, wasm: &[u8]) -> Result<Box<dyn Module + 'e>, Box<dyn Error>> {
+        let module = wasmi::Module::new(&self.engine, wasm)?;
+
+        Ok(Box::new(WasmiModule {
+            engine: &self.engine,
+            fuel: self.fuel,
+            fuel_per_second: &self.fuel_per_second,
+            module,
+        }))
+    }
+}
+
+struct WasmiModule<'e> {
+    engine: &'e wasmi::Engine,
+    fuel: bool,
+    fuel_per_second: &'e Arc<AtomicU64>,
+    module: wasmi::Module,
+}
+
+impl Module for WasmiModule<'_> {
+    fn instantiate(&mut self) -> Result<Box<dyn Instance + '_>, Box<dyn Error>> {
+        self.instantiate_shared()
+    }
+
+    fn shared(&self) -> Option<&dyn SharedModule> {
+        Some(self)
+    }
+}
 
-    let run = instance.get_typed_func::<(), f32>(&store, "run")?;
+impl SharedModule for WasmiModule<'_> {
+    fn instantiate_shared(&self) -> Result<Box<dyn Instance + '_>, Box<dyn Error>> {
+        let mut store = Store::new(self.engine, ());
+        if self.fuel {
+            store.set_fuel(u64::MAX).map_err(|e| e.to_string())?;
+        }
+        let clock_ms = Func::wrap(&mut store, clock_ms);
+        let clock_ns = Func::wrap(&mut store, clock_ns);
+
+        let mut linker = <Linker<()>>::new(self.engine);
+        linker.define("env", "clock_ms", clock_ms)?;
+        linker.define("env", "clock_ns", clock_ns)?;
+        let instance = linker
+            .instantiate(&mut store, &self.module)?
+            .start(&mut store)?;
+
+        Ok(Box::new(WasmiInstance {
+            store,
+            instance,
+            fuel: self.fuel,
+            deadline: None,
+            fuel_per_second: self.fuel_per_second.clone(),
+        }))
+    }
+}
+
+struct WasmiInstance {
+    store: Store<()>,
+    instance: wasmi::Instance,
+    fuel: bool,
+    deadline: Option<Instant>,
+    fuel_per_second: Arc<AtomicU64>,
+}
+
+impl Instance for WasmiInstance {
+    fn invoke(&mut self, export: &str, args: &[Value]) -> Result<Value, Box<dyn Error>> {
+        let func = self
+            .instance
+            .get_func(&self.store, export)
+            .ok_or_else(|| format!("export `{}` not found", export))?;
+
+        let params: Vec<Val> = args.iter().map(|&arg| to_val(arg)).collect();
+        let mut results = vec![Val::I32(0); func.ty(&self.store).results().len()];
+
+        let Some(deadline) = self.deadline else {
+            func.call(&mut self.store, &params, &mut results)?;
+            return first_result(export, &results);
+        };
+
+        // Twice the measured rate leaves room for the guest speeding up between calls.
+        let rate = match f64::from_bits(self.fuel_per_second.load(Ordering::Relaxed)) {
+            rate if rate > 0.0 => rate * 2.0,
+            _ => MAX_FUEL_PER_SECOND,
+        };
+        let remaining = deadline.saturating_duration_since(Instant::now()).as_secs_f64();
+        let budget = remaining * rate;
+        self.store.set_fuel(budget as u64).map_err(|e| e.to_string())?;
+
+        let start = Instant::now();
+        func.call(&mut self.store, &params, &mut results)?;
+        let elapsed = start.elapsed();
+
+        if elapsed >= MIN_RATE_SAMPLE {
+            let burnt = budget as u64 - self.store.get_fuel().map_err(|e| e.to_string())?;
+            let rate = burnt as f64 / elapsed.as_secs_f64();
+            self.fuel_per_second.fetch_max(rate.to_bits(), Ordering::Relaxed);
+        }
+        first_result(export, &results)
+    }
+
+    fn set_deadline(&mut self, deadline: Option<Instant>) -> bool {
+        if !self.fuel {
+            return false;
+        }
+        self.deadline = deadline;
+        if deadline.is_none() {
+            // Calls outside a deadline run on unlimited fuel again.
+            let _ = self.store.set_fuel(u64::MAX);
+        }
+        true
+    }
+
+    fn memory(&mut self, export: &str) -> Option<Vec<u8>> {
+        let memory = self.instance.get_memory(&self.store, export)?;
+        Some(memory.data(&self.store).to_vec())
+    }
+}
+
+fn first_result(export: &str, results: &[Val]) -> Result<Value, Box<dyn Error>> {
+    results
+        .first()
+        .and_then(from_val)
+        .ok_or_else(|| {
+            UnexpectedResult {
+                export: export.to_string(),
+                value: None,
+            }
+            .into()
+        })
+}
+
+fn to_val(value: Value) -> Val {
+    match value {
+        Value::I32(v) => Val::I32(v),
+        Value::I64(v) => Val::I64(v),
+        Value::F32(v) => Val::F32(v.into()),
+        Value::F64(v) => Val::F64(v.into()),
+    }
+}
 
-    Ok(run.call(&mut store, ())?)
+fn from_val(val: &Val) -> Option<Value> {
+    match *val {
+        Val::I32(v) => Some(Value::I32(v)),
+        Val::I64(v) => Some(Value::I64(v)),
+        Val::F32(v) => Some(Value::F32(v.into())),
+        Val::F64(v) => Some(Value::F64(v.into())),
+        _ => None,
+    }
 }
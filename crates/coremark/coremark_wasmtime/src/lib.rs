@@ -1,27 +1,266 @@
+use std::borrow::Cow;
 use std::error::Error;
 use std::result::Result;
-use std::time::{SystemTime, UNIX_EPOCH};
 
-use wasmtime::*;
+use coremark_engine::{
+    clock_ms, clock_ns, Capabilities, Engine, Instance, InterruptHandle, Module, SharedModule,
+    UnexpectedResult, Value,
+};
+#[cfg(feature = "winch")]
+use wasmtime::Strategy;
+use wasmtime::{
+    Config, InstanceAllocationStrategy, Linker, OptLevel, PoolingAllocationConfig, Store, Val,
+};
 
-pub fn wasmtime_container(b: &[u8]) -> Result<f32, Box<dyn Error>> {
-    let engine = Engine::default();
+/// Named engine configuration, each benchmarked as its own engine.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Preset {
+    /// Cranelift optimising for speed, wasmtime's default.
+    Default,
+    /// Cranelift without optimisations.
+    OptNone,
+    /// Cranelift optimising for speed and code size.
+    OptSize,
+    /// The Winch baseline compiler.
+    #[cfg(feature = "winch")]
+    Winch,
+    /// No memory reservation and no guard pages, which forces an explicit bounds check on every
+    /// memory access.
+    BoundsChecks,
+    /// Functions are compiled one after another instead of in parallel.
+    SerialCompilation,
+    /// Instances are allocated from a pool reserved up front.
+    Pooling,
+}
+
+impl Preset {
+    pub fn name(self) -> &'static str {
+        match self {
+            Preset::Default => "wasmtime",
+            Preset::OptNone => "wasmtime-opt-none",
+            Preset::OptSize => "wasmtime-opt-size",
+            #[cfg(feature = "winch")]
+            Preset::Winch => "wasmtime-winch",
+            Preset::BoundsChecks => "wasmtime-bounds-checks",
+            Preset::SerialCompilation => "wasmtime-serial-compilation",
+            Preset::Pooling => "wasmtime-pooling",
+        }
+    }
+
+    fn configure(self, config: &mut Config) {
+        match self {
+            Preset::Default => {}
+            Preset::OptNone => {
+                config.cranelift_opt_level(OptLevel::None);
+            }
+            Preset::OptSize => {
+                config.cranelift_opt_level(OptLevel::SpeedAndSize);
+            }
+            #[cfg(feature = "winch")]
+            Preset::Winch => {
+                config.strategy(Strategy::Winch);
+            }
+            Preset::BoundsChecks => {
+                config
+                    .memory_reservation(0)
+                    .memory_reservation_for_growth(0)
+                    .memory_guard_size(0);
+            }
+            Preset::SerialCompilation => {
+                config.parallel_compilation(false);
+            }
+            Preset::Pooling => {
+                config.allocation_strategy(InstanceAllocationStrategy::Pooling(PoolingAllocationConfig::default()));
+            }
+        }
+    }
+}
+
+pub struct WasmtimeEngine {
+    name: String,
+    // A configuration the host cannot run is reported when compiling, as a failed run of this engine.
+    engine: Result<wasmtime::Engine, String>,
+    epoch_interruption: bool,
+    aot: bool,
+}
+
+impl WasmtimeEngine {
+    pub fn new() -> Self {
+        Self::with_preset(Preset::Default, false)
+    }
 
-    let module = Module::new(&engine, &b[..])?;
+    /// `epoch_interruption` compiles epoch checks into the guest so runs can be interrupted, at
+    /// some cost in speed.
+    pub fn with_preset(preset: Preset, epoch_interruption: bool) -> Self {
+        let mut config = Config::new();
+        config.epoch_interruption(epoch_interruption);
+        preset.configure(&mut config);
 
-    let mut store = Store::<u32>::new(&engine, 64);
+        Self {
+            name: preset.name().to_string(),
+            engine: wasmtime::Engine::new(&config).map_err(|e| e.to_string()),
+            epoch_interruption,
+            aot: false,
+        }
+    }
 
-    let mut linker = Linker::new(&engine);
-    linker.func_wrap("env", "clock_ms", || {
-        SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .expect("Clock may have gone backwards")
-            .as_millis() as i64
-    })?;
+    /// Builds a `.cwasm` image with `Engine::precompile_module` up front, without creating a module, so
+    /// `compile` only maps the image into the engine.
+    #[cfg(feature = "aot")]
+    pub fn aot(mut self) -> Self {
+        self.name.push_str("-aot");
+        self.aot = true;
+        self
+    }
 
-    let instance = linker.instantiate(&mut store, &module)?;
+    fn engine(&self) -> Result<&wasmtime::Engine, Box<dyn Error>> {
+        self.engine.as_ref().map_err(|e| e.as_str().into())
+    }
+}
+
+impl Default for WasmtimeEngine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Engine for WasmtimeEngine {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn version(&self) -> &str {
+        "28.0"
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            interruptible: self.epoch_interruption,
+            shared_module: true,
+        }
+    }
+
+    fn precompile<'w>(&self, wasm: &'w [u8]) -> Result<Cow<'w, [u8]>, Box<dyn Error>> {
+        if !self.aot {
+            return Ok(Cow::Borrowed(wasm));
+        }
+        Ok(Cow::Owned(self.engine()?.precompile_module(wasm)?))
+    }
+
+    fn compile<'e>(&'e self, wasm: &[u8]) -> Result<Box<dyn Module + 'e>, Box<dyn Error>> {
+        let engine = self.engine()?;
+        let module = if self.aot {
+            // Safety: the image comes from `precompile_module` on this engine, so its settings match.
+            unsafe { wasmtime::Module::deserialize(engine, wasm)? }
+        } else {
+            wasmtime::Module::new(engine, wasm)?
+        };
+
+        Ok(Box::new(WasmtimeModule {
+            engine,
+            epoch_interruption: self.epoch_interruption,
+            module,
+        }))
+    }
+}
+
+struct WasmtimeModule<'e> {
+    engine: &'e wasmtime::Engine,
+    epoch_interruption: bool,
+    module: wasmtime::Module,
+}
 
-    let run = instance.get_typed_func::<(), f32>(&mut store, "run")?;
+impl Module for WasmtimeModule<'_> {
+    fn instantiate(&mut self) -> Result<Box<dyn Instance + '_>, Box<dyn Error>> {
+        self.instantiate_shared()
+    }
+
+    fn shared(&self) -> Option<&dyn SharedModule> {
+        Some(self)
+    }
+}
+
+impl SharedModule for WasmtimeModule<'_> {
+    fn instantiate_shared(&self) -> Result<Box<dyn Instance + '_>, Box<dyn Error>> {
+        let mut store = Store::new(self.engine, ());
+        if self.epoch_interruption {
+            // The epoch only advances when interrupted, so any increment traps the running call.
+            store.set_epoch_deadline(1);
+        }
+
+        let mut linker = Linker::new(self.engine);
+        linker.func_wrap("env", "clock_ms", clock_ms)?;
+        linker.func_wrap("env", "clock_ns", clock_ns)?;
+
+        let instance = linker.instantiate(&mut store, &self.module)?;
+
+        Ok(Box::new(WasmtimeInstance {
+            store,
+            instance,
+            epoch_interruption: self.epoch_interruption,
+        }))
+    }
+}
+
+struct WasmtimeInstance {
+    store: Store<()>,
+    instance: wasmtime::Instance,
+    epoch_interruption: bool,
+}
+
+impl Instance for WasmtimeInstance {
+    fn invoke(&mut self, export: &str, args: &[Value]) -> Result<Value, Box<dyn Error>> {
+        let func = self
+            .instance
+            .get_func(&mut self.store, export)
+            .ok_or_else(|| format!("export `{}` not found", export))?;
+
+        let params: Vec<Val> = args.iter().map(|&arg| to_val(arg)).collect();
+        let mut results = vec![Val::I32(0); func.ty(&self.store).results().len()];
+        func.call(&mut self.store, &params, &mut results)?;
+
+        results
+            .first()
+            .and_then(from_val)
+            .ok_or_else(|| {
+                UnexpectedResult {
+                    export: export.to_string(),
+                    value: None,
+                }
+                .into()
+            })
+    }
+
+    fn interrupt_handle(&mut self) -> Option<InterruptHandle> {
+        if !self.epoch_interruption {
+            return None;
+        }
+
+        let engine = self.store.engine().clone();
+        Some(InterruptHandle::new(move || engine.increment_epoch()))
+    }
+
+    fn memory(&mut self, export: &str) -> Option<Vec<u8>> {
+        let memory = self.instance.get_memory(&mut self.store, export)?;
+        Some(memory.data(&self.store).to_vec())
+    }
+}
+
+fn to_val(value: Value) -> Val {
+    match value {
+        Value::I32(v) => Val::I32(v),
+        Value::I64(v) => Val::I64(v),
+        Value::F32(v) => Val::F32(v.to_bits()),
+        Value::F64(v) => Val::F64(v.to_bits()),
+    }
+}
 
-    Ok(run.call(&mut store, ())?)
+fn from_val(val: &Val) -> Option<Value> {
+    match *val {
+        Val::I32(v) => Some(Value::I32(v)),
+        Val::I64(v) => Some(Value::I64(v)),
+        Val::F32(v) => Some(Value::F32(f32::from_bits(v))),
+        Val::F64(v) => Some(Value::F64(f64::from_bits(v))),
+        _ => None,
+    }
 }
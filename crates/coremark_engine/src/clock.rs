@@ -0,0 +1,14 @@
+use std::sync::OnceLock;
+use std::time::Instant;
+
+static EPOCH: OnceLock<Instant> = OnceLock::new();
+
+/// Nanoseconds on a monotonic clock, counted from the first time any clock import is called.
+pub fn clock_ns() -> i64 {
+    EPOCH.get_or_init(Instant::now).elapsed().as_nanos() as i64
+}
+
+/// Milliseconds on the same clock as `clock_ns`, for guests built against the older import.
+pub fn clock_ms() -> i64 {
+    clock_ns() / 1_000_000
+}
@@ -0,0 +1,177 @@
+use std::borrow::Cow;
+use std::error::Error;
+use std::fmt;
+use std::result::Result;
+use std::time::Instant;
+
+mod clock;
+
+pub use clock::{clock_ms, clock_ns};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Value {
+    I32(i32),
+    I64(i64),
+    F32(f32),
+    F64(f64),
+}
+
+impl Value {
+    pub fn as_f32(&self) -> Option<f32> {
+        match *self {
+            Value::F32(v) => Some(v),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::I32(v) => write!(f, "{}", v),
+            Value::I64(v) => write!(f, "{}", v),
+            Value::F32(v) => write!(f, "{}", v),
+            Value::F64(v) => write!(f, "{}", v),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Capabilities {
+    /// Instances stop a running call at a deadline, through an `InterruptHandle` or by themselves.
+    pub interruptible: bool,
+    /// Several threads can instantiate one compiled module and run it concurrently.
+    pub shared_module: bool,
+}
+
+pub trait Engine {
+    fn name(&self) -> &str;
+
+    fn version(&self) -> &str;
+
+    fn capabilities(&self) -> Capabilities;
+
+    /// Ahead-of-time compiles `wasm` into the artifact handed to `compile`, outside the timed
+    /// phases. Engines without an AOT mode pass the module through unchanged.
+    fn precompile<'w>(&self, wasm: &'w [u8]) -> Result<Cow<'w, [u8]>, Box<dyn Error>> {
+        Ok(Cow::Borrowed(wasm))
+    }
+
+    fn compile<'e>(&'e self, wasm: &[u8]) -> Result<Box<dyn Module + 'e>, Box<dyn Error>>;
+}
+
+pub trait Module {
+    fn instantiate(&mut self) -> Result<Box<dyn Instance + '_>, Box<dyn Error>>;
+
+    fn shared(&self) -> Option<&dyn SharedModule> {
+        None
+    }
+}
+
+/// A compiled module that threads instantiate concurrently, each instance in its own store.
+pub trait SharedModule: Sync {
+    fn instantiate_shared(&self) -> Result<Box<dyn Instance + '_>, Box<dyn Error>>;
+}
+
+pub trait Instance {
+    fn invoke(&mut self, export: &str, args: &[Value]) -> Result<Value, Box<dyn Error>>;
+
+    fn interrupt_handle(&mut self) -> Option<InterruptHandle> {
+        None
+    }
+
+    /// Bounds the following calls by `deadline`, for engines that stop a call themselves rather
+    /// than through an `InterruptHandle`. Returns whether the engine can, `None` lifts the bound.
+    fn set_deadline(&mut self, _deadline: Option<Instant>) -> bool {
+        false
+    }
+
+    /// Copy of the exported memory named `export`, for engines able to read guest memory.
+    fn memory(&mut self, _export: &str) -> Option<Vec<u8>> {
+        None
+    }
+}
+
+/// Makes a call running on an instance trap, from any thread.
+pub struct InterruptHandle(Box<dyn Fn() + Send + Sync>);
+
+impl InterruptHandle {
+    pub fn new(interrupt: impl Fn() + Send + Sync + 'static) -> Self {
+        Self(Box::new(interrupt))
+    }
+
+    pub fn interrupt(&self) {
+        (self.0)()
+    }
+}
+
+#[derive(Default)]
+pub struct Registry {
+    engines: Vec<Box<dyn Engine>>,
+    unavailable: Vec<(&'static str, String)>,
+}
+
+impl Registry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn register<E: Engine + 'static>(&mut self, engine: E) -> &mut Self {
+        self.engines.push(Box::new(engine));
+        self
+    }
+
+    /// Registers `engine`, or records why `name` cannot run on this host.
+    pub fn try_register<E: Engine + 'static>(
+        &mut self,
+        name: &'static str,
+        engine: Result<E, Box<dyn Error>>,
+    ) -> &mut Self {
+        match engine {
+            Ok(engine) => self.register(engine),
+            Err(e) => self.unavailable(name, e.to_string()),
+        }
+    }
+
+    /// Records an engine the harness knows about but cannot run, such as one not compiled in.
+    pub fn unavailable(&mut self, name: &'static str, reason: String) -> &mut Self {
+        self.unavailable.push((name, reason));
+        self
+    }
+
+    /// Why `name` cannot run, if it is a known engine that is unavailable.
+    pub fn unavailable_reason(&self, name: &str) -> Option<&str> {
+        self.iter_unavailable()
+            .find(|&(unavailable, _)| unavailable == name)
+            .map(|(_, reason)| reason)
+    }
+
+    pub fn iter_unavailable(&self) -> impl Iterator<Item = (&'static str, &str)> {
+        self.unavailable.iter().map(|(name, reason)| (*name, reason.as_str()))
+    }
+
+    pub fn get(&self, name: &str) -> Option<&dyn Engine> {
+        self.iter().find(|engine| engine.name() == name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &dyn Engine> {
+        self.engines.iter().map(|engine| engine.as_ref())
+    }
+}
+
+#[derive(Debug)]
+pub struct UnexpectedResult {
+    pub export: String,
+    pub value: Option<Value>,
+}
+
+impl fmt::Display for UnexpectedResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.value {
+            Some(value) => write!(f, "unexpected result {:?} from `{}`", value, self.export),
+            None => write!(f, "`{}` returned no value", self.export),
+        }
+    }
+}
+
+impl Error for UnexpectedResult {}
@@ -38,85 +38,125 @@ mod list;
 mod matrix;
 mod state;
 
+use alloc::vec::Vec;
 use list::LinkedList;
 use matrix::Matrix;
 use state::State;
 
 #[link(wasm_import_module = "env")]
 extern "C" {
-    fn clock_ms() -> i64;
+    fn clock_ns() -> i64;
 }
 
 static mut CRC: u16 = 0;
+static mut CRC_LIST: u16 = 0;
+static mut CRC_MATRIX: u16 = 0;
+static mut CRC_STATE: u16 = 0;
 
+// Inputs of the list, matrix and state kernels are derived from seed1, seed2 and seed3, which
+// the host passes at call time so they cannot be constant-folded.
 #[export_name = "run"]
-pub fn run() -> f32 {
-    let mut timer_ms: i64;
+pub extern "C" fn run(iterations: u32, seed1: i32, seed2: i32, seed3: i32) -> f32 {
+    let tokens = state_tokens(seed3);
+    let mut timer_ns: i64;
 
     unsafe {
-        timer_ms = clock_ms();
+        timer_ns = clock_ns();
 
-        let iterations = 100_000;
         let mut crc: u16 = 0;
 
         for _ in 0..iterations {
-            benchmark_list(&mut crc);
-            benchmark_matrix(&mut crc);
-            benchmark_state(&mut crc);
+            CRC_LIST = benchmark_list(seed1 as i16);
+            CRC_MATRIX = benchmark_matrix(seed2 as i16);
+            CRC_STATE = benchmark_state(&tokens);
+
+            crc = crc16(&CRC_LIST.to_le_bytes(), crc);
+            crc = crc16(&CRC_MATRIX.to_le_bytes(), crc);
+            crc = crc16(&CRC_STATE.to_le_bytes(), crc);
         }
-        timer_ms = clock_ms() - timer_ms;
+        timer_ns = clock_ns() - timer_ns;
 
-        CRC = crc16(&crc.to_le_bytes(), CRC);
+        CRC = crc;
     }
 
-    timer_ms as f32
+    (timer_ns as f64 / 1_000_000.0) as f32
+}
+
+#[export_name = "crc"]
+pub extern "C" fn crc() -> u32 {
+    unsafe { CRC as u32 }
+}
+
+#[export_name = "crc_list"]
+pub extern "C" fn crc_list() -> u32 {
+    unsafe { CRC_LIST as u32 }
+}
+
+#[export_name = "crc_matrix"]
+pub extern "C" fn crc_matrix() -> u32 {
+    unsafe { CRC_MATRIX as u32 }
+}
+
+#[export_name = "crc_state"]
+pub extern "C" fn crc_state() -> u32 {
+    unsafe { CRC_STATE as u32 }
 }
 
-fn benchmark_list(crc: &mut u16) {
+fn benchmark_list(seed: i16) -> u16 {
+    let mut crc: u16 = 0;
     let mut list = LinkedList::<i16>::new();
-    let size = 10;
+    let size = 10 + (seed as u16 % 7) as i16;
 
     for i in 0..size {
-        list.push_front(i);
+        list.push_front(i ^ seed);
     }
 
-    *crc = crc16(&(list.size() as u32).to_le_bytes(), *crc);
+    crc = crc16(&(list.size() as u32).to_le_bytes(), crc);
 
     list.reverse();
 
-    if let Some(found) = list.find(|&x| x == (size / 2) as i16) {
-        *crc = crc16(&found.to_le_bytes(), *crc);
+    let middle = (size / 2) ^ seed;
+    if let Some(found) = list.find(|&x| x == middle) {
+        crc = crc16(&found.to_le_bytes(), crc);
     }
 
     list.mergesort(&|a, b| a.cmp(b));
 
-    list.insert_after(&(size / 2 as i16), size * 2);
+    let marker = (size * 2) ^ seed;
+    list.insert_after(&middle, marker);
 
-    if let Some(removed_val) = list.remove_after(&(size * 2)) {
-        *crc = crc16(&removed_val.to_le_bytes(), *crc);
+    if let Some(removed_val) = list.remove_after(&marker) {
+        crc = crc16(&removed_val.to_le_bytes(), crc);
     }
 
     while let Some(v) = list.pop_front() {
-        if v == 0 {
-            *crc = crc16(&v.to_le_bytes(), *crc);
+        if v == seed {
+            crc = crc16(&v.to_le_bytes(), crc);
         }
     }
+
+    crc
 }
 
-fn benchmark_matrix(crc: &mut u16) {
-    let size = 3;
+fn benchmark_matrix(seed: i16) -> u16 {
+    let mut crc: u16 = 0;
+    let size = 3 + (seed as u16 % 3) as usize;
     let mut matrix_a = Matrix::<i16>::new(size, size);
     let mut matrix_b = Matrix::<i16>::new(size, size);
 
+    // Matrix arithmetic is not wrapping, so all 16 bits of the seed are folded into a small offset
+    // that keeps the 5x5 products within i16.
+    let bits = seed as u16;
+    let offset = ((bits ^ bits >> 5 ^ bits >> 10 ^ bits >> 15) & 0x1f) as i16;
     for i in 0..size {
         for j in 0..size {
-            matrix_a.set(i, j, (i + j) as i16);
-            matrix_b.set(i, j, (i - j) as i16);
+            matrix_a.set(i, j, (i + j) as i16 + offset);
+            matrix_b.set(i, j, i as i16 - j as i16 + offset);
         }
     }
 
     let mid_val = matrix_a.get(1, 1);
-    *crc = crc16(&mid_val.to_le_bytes(), *crc);
+    crc = crc16(&mid_val.to_le_bytes(), crc);
 
     matrix_a.mul_const(2);
     matrix_b.add_const(-2);
@@ -124,11 +164,12 @@ fn benchmark_matrix(crc: &mut u16) {
     let inv_a = matrix_a.inverse();
     if let Some(inv) = inv_a {
         let inv_val = inv.get(1, 1);
-        *crc = crc16(&inv_val.to_le_bytes(), *crc);
+        crc = crc16(&inv_val.to_le_bytes(), crc);
     }
 
-    let vect_res = matrix_a.mul_vect(&[1, 2, 3]);
-    *crc = crc16(&vect_res[1].to_le_bytes(), *crc);
+    let vector: Vec<i16> = (1..=size as i16).map(|k| offset + k).collect();
+    let vect_res = matrix_a.mul_vect(&vector);
+    crc = crc16(&vect_res[1].to_le_bytes(), crc);
 
     let mat_c = matrix_a.mul_matrix(&matrix_b);
 
@@ -138,16 +179,52 @@ fn benchmark_matrix(crc: &mut u16) {
             sum = sum.wrapping_add(mat_c.get(i, j));
         }
     }
-    *crc = crc16(&sum.to_le_bytes(), *crc);
+    crc = crc16(&sum.to_le_bytes(), crc);
+
+    crc
+}
+
+// Shapes of the tokens fed to the state kernel, each `#` becomes a digit.
+const TOKEN_PATTERNS: [&[u8]; 8] = [
+    b"###.##e-#",
+    b"###",
+    b"inv#lid",
+    b"##e#",
+    b".#",
+    b"-#.#",
+    b"+#e+##",
+    b"#.#.#",
+];
+
+fn state_tokens(seed: i32) -> Vec<Vec<u8>> {
+    let mut rng = seed as u32;
+    let mut next = || {
+        rng = rng.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+        (rng >> 16) as usize
+    };
+
+    let mut tokens = Vec::with_capacity(5);
+    for _ in 0..5 {
+        let pattern = TOKEN_PATTERNS[next() % TOKEN_PATTERNS.len()];
+        let mut token = Vec::with_capacity(pattern.len());
+        for &ch in pattern {
+            token.push(if ch == b'#' { b'0' + (next() % 10) as u8 } else { ch });
+        }
+        tokens.push(token);
+    }
+    tokens
 }
 
-fn benchmark_state(crc: &mut u16) {
-    for token in ["123.45e-6", "678", "invalid", "42e2", ".5"] {
-        let (final_state, path) = State::transition(token.as_bytes());
-        *crc = crc16(&[(final_state as u8)], *crc);
+fn benchmark_state(tokens: &[Vec<u8>]) -> u16 {
+    let mut crc: u16 = 0;
+    for token in tokens {
+        let (final_state, path) = State::transition(token);
+        crc = crc16(&[(final_state as u8)], crc);
         let path_len = path.len() as u32;
-        *crc = crc16(&path_len.to_le_bytes(), *crc);
+        crc = crc16(&path_len.to_le_bytes(), crc);
     }
+
+    crc
 }
 
 fn crc16(data: &[u8], mut crc: u16) -> u16 {
@@ -163,3 +240,22 @@ fn crc16(data: &[u8], mut crc: u16) -> u16 {
     }
     crc
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The harness validates engines against these values, keep `src/validate.rs` in sync.
+    #[test]
+    fn test_kernel_crcs() {
+        for ((seed1, seed2, seed3), (list, matrix, state)) in [
+            ((0, 0, 0x66), (0xf059, 0x0f94, 0x4a47)),
+            ((0x3415, 0x3415, 0x66), (0x51d2, 0xb04a, 0x4a47)),
+            ((8, 8, 8), (0x9e34, 0xe4ff, 0x73d4)),
+        ] {
+            assert_eq!(benchmark_list(seed1), list);
+            assert_eq!(benchmark_matrix(seed2), matrix);
+            assert_eq!(benchmark_state(&state_tokens(seed3)), state);
+        }
+    }
+}
@@ -0,0 +1,407 @@
+use std::borrow::Cow;
+use std::error::Error;
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::result::Result;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::Barrier;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use coremark_engine::{Engine, Instance, UnexpectedResult, Value};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Sample {
+    /// Iterations performed by the measured call, calibrated per engine with `--target-time`.
+    #[serde(default)]
+    pub iterations: u32,
+    /// Milliseconds reported by the guest's own timer.
+    #[serde(rename = "result_ms")]
+    pub result: f32,
+    /// Host time of the engine's ahead-of-time compilation, zero for engines without one.
+    #[serde(rename = "precompile_ms", with = "millis", default)]
+    pub precompile: Duration,
+    #[serde(rename = "compile_ms", with = "millis")]
+    pub compile: Duration,
+    #[serde(rename = "instantiate_ms", with = "millis")]
+    pub instantiate: Duration,
+    /// Host time of each warmup call made on the instance before the measured one.
+    #[serde(rename = "warmup_ms", with = "millis::vec")]
+    pub warmup: Vec<Duration>,
+    #[serde(rename = "execute_ms", with = "millis")]
+    pub execute: Duration,
+    /// Value of the guest's `crc` export after the measured call, if it has one.
+    pub crc: Option<u16>,
+    /// CRCs of the last iteration of each kernel, for guests exporting `crc_list`, `crc_matrix`
+    /// and `crc_state`.
+    #[serde(default)]
+    pub kernel_crcs: Option<KernelCrcs>,
+    /// FNV-1a hash of the exported `memory` after the measured call, if the engine can read it.
+    #[serde(default)]
+    pub memory_digest: Option<u64>,
+}
+
+#[cfg(test)]
+impl Sample {
+    /// One iteration that the guest timed at `result` milliseconds, with no phase timings or CRCs.
+    pub fn new(result: f32) -> Self {
+        Sample {
+            iterations: 1,
+            result,
+            precompile: Duration::ZERO,
+            compile: Duration::ZERO,
+            instantiate: Duration::ZERO,
+            warmup: vec![],
+            execute: Duration::ZERO,
+            crc: None,
+            kernel_crcs: None,
+            memory_digest: None,
+        }
+    }
+}
+
+/// One concurrent run of an instance per thread, all sharing one compiled module.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ParallelRun {
+    /// Host time from releasing the threads until the last one returned.
+    #[serde(rename = "wall_ms", with = "millis")]
+    pub wall: Duration,
+    /// Milliseconds reported by the guest of each thread.
+    #[serde(rename = "results_ms")]
+    pub results: Vec<f32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KernelCrcs {
+    pub list: u16,
+    pub matrix: u16,
+    pub state: u16,
+}
+
+impl Sample {
+    /// Difference in percent between the guest-reported time and the host-timed call.
+    pub fn divergence(&self) -> f64 {
+        let execute = self.execute.as_secs_f64() * 1000.0;
+        if execute == 0.0 {
+            return 0.0;
+        }
+        (self.result as f64 - execute).abs() / execute * 100.0
+    }
+}
+
+/// Indices of the samples whose guest and host times differ by more than `threshold` percent.
+pub fn diverged(samples: &[Sample], threshold: f64) -> Vec<usize> {
+    samples
+        .iter()
+        .enumerate()
+        .filter(|(_, sample)| sample.divergence() > threshold)
+        .map(|(i, _)| i)
+        .collect()
+}
+
+mod millis {
+    use std::time::Duration;
+
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_f64(duration.as_secs_f64() * 1000.0)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        let ms = f64::deserialize(deserializer)?;
+        Duration::try_from_secs_f64(ms / 1000.0).map_err(D::Error::custom)
+    }
+
+    pub mod vec {
+        use std::time::Duration;
+
+        use serde::{de::Error, ser::SerializeSeq, Deserialize, Deserializer, Serializer};
+
+        pub fn serialize<S: Serializer>(durations: &[Duration], serializer: S) -> Result<S::Ok, S::Error> {
+            let mut seq = serializer.serialize_seq(Some(durations.len()))?;
+            for duration in durations {
+                seq.serialize_element(&(duration.as_secs_f64() * 1000.0))?;
+            }
+            seq.end()
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Duration>, D::Error> {
+            Vec::<f64>::deserialize(deserializer)?
+                .into_iter()
+                .map(|ms| Duration::try_from_secs_f64(ms / 1000.0).map_err(D::Error::custom))
+                .collect()
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Options {
+    pub iterations: u32,
+    /// Guest-reported time one run is calibrated to take, overriding `iterations`.
+    pub target: Option<Duration>,
+    pub seeds: [i32; 3],
+    pub warmup: u32,
+    pub repetitions: u32,
+    /// Wall-clock budget for all repetitions of one engine.
+    pub timeout: Option<Duration>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub enum Fault {
+    Error(String),
+    Timeout,
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Fault::Error(error) => f.write_str(error),
+            Fault::Timeout => f.write_str("timed out"),
+        }
+    }
+}
+
+impl From<Box<dyn Error>> for Fault {
+    fn from(error: Box<dyn Error>) -> Self {
+        Fault::Error(error.to_string())
+    }
+}
+
+/// Runs `call` on `instance`, interrupting it through the engine if it is still running at `deadline`.
+fn guarded<T>(
+    instance: &mut dyn Instance,
+    deadline: Option<Instant>,
+    call: impl FnOnce(&mut dyn Instance) -> Result<T, Box<dyn Error>>,
+) -> Result<T, Fault> {
+    let (deadline, handle) = match deadline {
+        Some(deadline) if Instant::now() >= deadline => return Err(Fault::Timeout),
+        Some(deadline) => (deadline, instance.interrupt_handle()),
+        None => return Ok(call(instance)?),
+    };
+
+    // Without an interrupt handle the engine either stops the call itself, reporting an error past
+    // the deadline, or is only run in-process when no timeout is set and is isolated otherwise.
+    let Some(handle) = handle else {
+        let bounded = instance.set_deadline(Some(deadline));
+        let result = call(instance);
+        if bounded {
+            instance.set_deadline(None);
+            if result.is_err() && Instant::now() >= deadline {
+                return Err(Fault::Timeout);
+            }
+        }
+        return Ok(result?);
+    };
+
+    let (cancel, cancelled) = mpsc::channel::<()>();
+    let watchdog = thread::spawn(move || {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        let fired = matches!(cancelled.recv_timeout(remaining), Err(RecvTimeoutError::Timeout));
+        if fired {
+            handle.interrupt();
+        }
+        fired
+    });
+
+    let result = call(instance);
+    drop(cancel);
+
+    if watchdog.join().unwrap_or(false) {
+        return Err(Fault::Timeout);
+    }
+    Ok(result?)
+}
+
+fn digest(data: &[u8]) -> u64 {
+    data.iter()
+        .fold(0xcbf2_9ce4_8422_2325, |hash, &byte| (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3))
+}
+
+fn run_args(iterations: u32, seeds: [i32; 3]) -> [Value; 4] {
+    let [seed1, seed2, seed3] = seeds;
+    [
+        Value::I32(iterations as i32),
+        Value::I32(seed1),
+        Value::I32(seed2),
+        Value::I32(seed3),
+    ]
+}
+
+fn elapsed_ms(result: Value) -> Result<f32, Fault> {
+    result.as_f32().ok_or_else(|| {
+        Fault::Error(
+            UnexpectedResult {
+                export: "run".to_string(),
+                value: Some(result),
+            }
+            .to_string(),
+        )
+    })
+}
+
+/// Probes with tenfold growing iteration counts until a call takes a hundredth of `target`, then
+/// extrapolates the iteration count whose guest-reported time is `target`.
+pub fn calibrate(
+    engine: &dyn Engine,
+    wasm: &[u8],
+    options: &Options,
+    target: Duration,
+    deadline: Option<Instant>,
+) -> Result<u32, Fault> {
+    let wasm = engine.precompile(wasm)?;
+    let mut module = engine.compile(&wasm)?;
+    let mut instance = module.instantiate()?;
+
+    let target_ms = target.as_secs_f64() * 1000.0;
+    let max = i32::MAX as u32;
+    let mut iterations = 10;
+    loop {
+        let args = run_args(iterations, options.seeds);
+        let result = guarded(instance.as_mut(), deadline, |instance| instance.invoke("run", &args))?;
+        let elapsed = elapsed_ms(result)? as f64;
+
+        if elapsed >= target_ms / 100.0 || iterations >= max / 10 {
+            let per_iteration = elapsed.max(f64::MIN_POSITIVE) / iterations as f64;
+            return Ok((target_ms / per_iteration).ceil().clamp(1.0, max as f64) as u32);
+        }
+        iterations *= 10;
+    }
+}
+
+pub fn run(
+    engine: &dyn Engine,
+    wasm: &[u8],
+    options: &Options,
+    deadline: Option<Instant>,
+) -> Result<Sample, Fault> {
+    let start = Instant::now();
+    let wasm = engine.precompile(wasm)?;
+    let precompile = match wasm {
+        Cow::Owned(_) => start.elapsed(),
+        Cow::Borrowed(_) => Duration::ZERO,
+    };
+
+    let start = Instant::now();
+    let mut module = engine.compile(&wasm)?;
+    let compile = start.elapsed();
+
+    let start = Instant::now();
+    let mut instance = module.instantiate()?;
+    let instantiate = start.elapsed();
+
+    let args = run_args(options.iterations, options.seeds);
+
+    let warmup = (0..options.warmup)
+        .map(|_| {
+            guarded(instance.as_mut(), deadline, |instance| {
+                let start = Instant::now();
+                instance.invoke("run", &args)?;
+                Ok(start.elapsed())
+            })
+        })
+        .collect::<Result<Vec<_>, Fault>>()?;
+
+    let (result, execute) = guarded(instance.as_mut(), deadline, |instance| {
+        let start = Instant::now();
+        let result = instance.invoke("run", &args)?;
+        Ok((result, start.elapsed()))
+    })?;
+
+    let result = elapsed_ms(result)?;
+
+    let mut crc = |export| match instance.invoke(export, &[]) {
+        Ok(Value::I32(crc)) => Some(crc as u16),
+        _ => None,
+    };
+    let kernel_crcs = match (crc("crc_list"), crc("crc_matrix"), crc("crc_state")) {
+        (Some(list), Some(matrix), Some(state)) => Some(KernelCrcs { list, matrix, state }),
+        _ => None,
+    };
+    let crc = crc("crc");
+    let memory_digest = instance.memory("memory").map(|memory| digest(&memory));
+
+    Ok(Sample {
+        iterations: options.iterations,
+        result,
+        precompile,
+        compile,
+        instantiate,
+        warmup,
+        execute,
+        crc,
+        kernel_crcs,
+        memory_digest,
+    })
+}
+
+pub fn bench(engine: &dyn Engine, wasm: &[u8], options: &Options) -> Result<Vec<Sample>, Fault> {
+    let deadline = options.timeout.map(|timeout| Instant::now() + timeout);
+
+    let mut options = *options;
+    if let Some(target) = options.target {
+        options.iterations = calibrate(engine, wasm, &options, target, deadline)?;
+    }
+
+    (0..options.repetitions)
+        .map(|_| run(engine, wasm, &options, deadline))
+        .collect()
+}
+
+/// Runs `threads` instances of one compiled module concurrently, once per repetition, after each
+/// instance made its warmup calls.
+pub fn parallel(
+    engine: &dyn Engine,
+    wasm: &[u8],
+    options: &Options,
+    threads: u32,
+    iterations: u32,
+) -> Result<Vec<ParallelRun>, Fault> {
+    let deadline = options.timeout.map(|timeout| Instant::now() + timeout);
+    let wasm = engine.precompile(wasm)?;
+    let module = engine.compile(&wasm)?;
+    let shared = module
+        .shared()
+        .ok_or_else(|| Fault::Error(format!("{} cannot share a module across threads", engine.name())))?;
+    let args = run_args(iterations, options.seeds);
+
+    (0..options.repetitions)
+        .map(|_| {
+            let barrier = Barrier::new(threads as usize + 1);
+            thread::scope(|scope| {
+                let handles: Vec<_> = (0..threads)
+                    .map(|_| {
+                        scope.spawn(|| {
+                            // Every thread reaches the barrier, even when it has nothing to run or panicked.
+                            let ready = panic::catch_unwind(AssertUnwindSafe(|| {
+                                let mut instance = shared.instantiate_shared()?;
+                                for _ in 0..options.warmup {
+                                    guarded(instance.as_mut(), deadline, |instance| instance.invoke("run", &args))?;
+                                }
+                                Ok::<_, Fault>(instance)
+                            }));
+                            barrier.wait();
+
+                            let mut instance = ready.unwrap_or_else(|payload| panic::resume_unwind(payload))?;
+                            let result = guarded(instance.as_mut(), deadline, |instance| instance.invoke("run", &args))?;
+                            elapsed_ms(result)
+                        })
+                    })
+                    .collect();
+
+                barrier.wait();
+                let start = Instant::now();
+                let results = handles
+                    .into_iter()
+                    .map(|handle| handle.join().unwrap_or_else(|_| Err(Fault::Error("thread panicked".to_string()))))
+                    .collect::<Result<Vec<f32>, Fault>>()?;
+
+                Ok(ParallelRun {
+                    wall: start.elapsed(),
+                    results,
+                })
+            })
+        })
+        .collect()
+}
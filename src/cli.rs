@@ -0,0 +1,119 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use clap::{Parser, ValueEnum};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    /// Markdown table, as consumed by the benchmark chart workflow.
+    Markdown,
+    /// One `engine: result` line per engine, then a CoreMark-style summary block for each.
+    Text,
+    /// Full report including every sample, see `report::Report`.
+    Json,
+    /// One line per sample.
+    Csv,
+}
+
+#[derive(Debug, Parser)]
+#[command(name = "coremark", version, about = "Coremark benchmark for wasm runtimes")]
+pub struct Args {
+    /// Engines to benchmark, comma separated [default: all registered engines]
+    #[arg(short, long, value_delimiter = ',')]
+    pub engine: Vec<String>,
+
+    /// Guest modules to run instead of the embedded coremark_wasm build
+    #[arg(short, long, num_args = 1..)]
+    pub module: Vec<PathBuf>,
+
+    /// Coremark iterations performed by each call to `run`
+    #[arg(short, long, default_value_t = 100_000)]
+    pub iterations: u32,
+
+    /// Calibrate the iterations of each engine so a run takes about this many seconds, instead of
+    /// using --iterations; CoreMark results are only valid for runs of at least 10 seconds
+    #[arg(long, value_name = "SECONDS", value_parser = parse_seconds, conflicts_with = "iterations")]
+    pub target_time: Option<Duration>,
+
+    /// CoreMark seeds passed to `run`, from which the guest derives the inputs of its kernels;
+    /// `0x3415,0x3415,0x66` and `8,8,8` select the validation and profile runs
+    #[arg(long, value_name = "SEED1,SEED2,SEED3", value_parser = parse_seeds, default_value = "0,0,0x66")]
+    pub seeds: [i32; 3],
+
+    /// Untimed calls to `run` on the same instance before the measured one
+    #[arg(short, long, default_value_t = 0)]
+    pub warmup: u32,
+
+    /// Number of times each engine is benchmarked
+    #[arg(short, long, default_value_t = 5, value_parser = clap::value_parser!(u32).range(1..))]
+    pub repetitions: u32,
+
+    /// Wall-clock limit in seconds for all runs of one engine; engines that cannot interrupt a
+    /// running guest are isolated in a worker that is killed at the limit
+    #[arg(short, long, value_name = "SECONDS", value_parser = parse_seconds)]
+    pub timeout: Option<Duration>,
+
+    /// Exclude runs outside 1.5 IQR of the quartiles from the summary (reported in the runs column)
+    #[arg(long)]
+    pub reject_outliers: bool,
+
+    /// Difference in percent between the guest-reported time and the host-timed call above which
+    /// a run is flagged as diverged
+    #[arg(long, value_name = "PERCENT", default_value_t = 10.0)]
+    pub max_divergence: f64,
+
+    /// Clock frequency of the CPU in MHz, to also report CoreMark/MHz
+    #[arg(long, value_name = "MHZ")]
+    pub cpu_mhz: Option<f64>,
+
+    /// Output format of the results
+    #[arg(short, long, value_enum, default_value_t = Format::Markdown)]
+    pub format: Format,
+
+    /// Also write the JSON report to this file, e.g. to keep it as a baseline
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+
+    /// Compare results with a JSON report saved earlier and fail on regressions
+    #[arg(long, value_name = "BASELINE")]
+    pub compare: Option<PathBuf>,
+
+    /// Drop in percent of an engine's median score that counts as a regression
+    #[arg(long, default_value_t = 5.0, requires = "compare")]
+    pub threshold: f64,
+
+    /// Also run this many instances of one compiled module concurrently, on engines that can share
+    /// a module across threads, and report the aggregate score and scaling efficiency
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..), conflicts_with = "isolate")]
+    pub threads: u32,
+
+    /// Run every engine on every module in its own subprocess, so a crash only fails that engine
+    #[arg(long)]
+    pub isolate: bool,
+
+    /// Benchmark a single engine and module and reply to the parent harness on stdout
+    #[arg(long, hide = true, conflicts_with = "isolate")]
+    pub worker: bool,
+
+    /// List available engines and exit
+    #[arg(short, long)]
+    pub list: bool,
+}
+
+fn parse_seconds(arg: &str) -> Result<Duration, String> {
+    let seconds: f64 = arg.parse().map_err(|e| format!("{}", e))?;
+    Duration::try_from_secs_f64(seconds).map_err(|e| format!("{}", e))
+}
+
+fn parse_seeds(arg: &str) -> Result<[i32; 3], String> {
+    let seeds = arg
+        .split(',')
+        .map(|seed| match seed.trim().strip_prefix("0x") {
+            Some(hex) => i32::from_str_radix(hex, 16),
+            None => seed.trim().parse(),
+        })
+        .collect::<Result<Vec<i32>, _>>()
+        .map_err(|e| format!("{}", e))?;
+
+    seeds.try_into().map_err(|_| "expected three comma separated seeds".to_string())
+}
@@ -0,0 +1,182 @@
+use std::error::Error;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+use std::result::Result;
+
+use crate::report::{Report, SCHEMA};
+
+#[derive(Debug, PartialEq)]
+pub struct Change {
+    pub engine: String,
+    pub module: String,
+    /// Median CoreMark scores, in iterations per second.
+    pub baseline: f64,
+    /// `None` when the engine failed or did not run in the current report.
+    pub current: Option<f64>,
+    /// Relative change of the median score in percent, negative when slower.
+    pub percent: Option<f64>,
+    /// Whether the current report lists the engine among its failures.
+    pub failed: bool,
+}
+
+pub fn load(path: &Path) -> Result<Report, Box<dyn Error>> {
+    let json: serde_json::Value = serde_json::from_slice(&fs::read(path)?)?;
+
+    let schema = json.get("schema").and_then(|schema| schema.as_u64());
+    if schema != Some(SCHEMA as u64) {
+        return Err(format!(
+            "report schema {} is not supported, expected {}",
+            schema.map_or_else(|| "(missing)".to_string(), |schema| schema.to_string()),
+            SCHEMA
+        )
+        .into());
+    }
+
+    Ok(serde_json::from_value(json)?)
+}
+
+/// Matches every engine of the baseline with the current report, including those that failed or are missing.
+pub fn changes(baseline: &Report, current: &Report) -> Vec<Change> {
+    baseline
+        .engines
+        .iter()
+        .filter_map(|base| {
+            let failed = current
+                .failures
+                .iter()
+                .any(|failure| failure.engine == base.engine && failure.module == base.module);
+            let row = current
+                .engines
+                .iter()
+                .find(|row| row.engine == base.engine && row.module == base.module)
+                .filter(|_| !failed);
+
+            let baseline = base.summary.score.median;
+            let (current, percent) = match row {
+                Some(_) if baseline <= 0.0 => return None,
+                Some(row) => {
+                    let current = row.summary.score.median;
+                    (Some(current), Some((current - baseline) / baseline * 100.0))
+                }
+                None => (None, None),
+            };
+
+            Some(Change {
+                engine: base.engine.clone(),
+                module: base.module.clone(),
+                baseline,
+                current,
+                percent,
+                failed,
+            })
+        })
+        .collect()
+}
+
+/// Writes the comparison and returns whether any engine regressed beyond `threshold` percent, failed or is missing.
+pub fn write(out: &mut dyn Write, changes: &[Change], threshold: f64) -> io::Result<bool> {
+    writeln!(out, "\nComparison with baseline (threshold {:.1}%):\n", threshold)?;
+    writeln!(out, "| Engine     | Module               | Baseline(it/s) | Current(it/s)  | Change     | Status     |\n|------------|----------------------|----------------|----------------|------------|------------|")?;
+
+    let mut regressed = false;
+    for change in changes {
+        let (current, percent, status) = match (change.current, change.percent) {
+            (Some(current), Some(percent)) => {
+                let status = if percent < -threshold {
+                    regressed = true;
+                    "regressed"
+                } else if percent > threshold {
+                    "improved"
+                } else {
+                    "unchanged"
+                };
+                (format!("{:.2}", current), format!("{:+.1}%", percent), status)
+            }
+            _ => {
+                regressed = true;
+                let status = if change.failed { "failed" } else { "missing" };
+                ("-".to_string(), "-".to_string(), status)
+            }
+        };
+        writeln!(
+            out,
+            "| {:<10} | {:<20} | {:<14.2} | {:<14} | {:<10} | {:<10} |",
+            change.engine, change.module, change.baseline, current, percent, status,
+        )?;
+    }
+
+    Ok(regressed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bench::Sample;
+    use crate::report::{Config, EngineReport, Failure};
+    use crate::validate::PERFORMANCE_SEEDS;
+
+    fn report(results: &[(&str, f32)]) -> Report {
+        let config = Config {
+            modules: vec!["embedded".to_string()],
+            iterations: 1,
+            target_ms: None,
+            seeds: PERFORMANCE_SEEDS,
+            warmup: 0,
+            repetitions: 1,
+            reject_outliers: false,
+            max_divergence: 10.0,
+            cpu_mhz: None,
+        };
+        let engines = results
+            .iter()
+            .map(|&(engine, result)| {
+                EngineReport::new(engine, "0", "embedded", &config, vec![Sample::new(result)], vec![], vec![])
+            })
+            .collect();
+
+        Report {
+            schema: SCHEMA,
+            config,
+            engines,
+            failures: vec![],
+        }
+    }
+
+    #[test]
+    fn test_changes() {
+        let baseline = report(&[
+            ("wasmtime", 100.0),
+            ("wasmi", 200.0),
+            ("wasm3", 0.0),
+            ("wamr", 50.0),
+            ("wasmer", 80.0),
+        ]);
+        let mut current = report(&[
+            ("wasmtime", 110.0),
+            ("wasmi", 150.0),
+            ("wasm3", 10.0),
+            ("wasmedge", 5.0),
+        ]);
+        current.failures.push(Failure {
+            engine: "wamr".to_string(),
+            module: "embedded".to_string(),
+            error: "trap".to_string(),
+            timed_out: false,
+        });
+
+        let changes = changes(&baseline, &current);
+        assert_eq!(changes.len(), 4);
+        assert_eq!(changes[0].engine, "wasmtime");
+        assert!((changes[0].percent.unwrap() + 100.0 / 11.0).abs() < 1e-9);
+        assert_eq!(changes[1].engine, "wasmi");
+        assert!((changes[1].percent.unwrap() - 100.0 / 3.0).abs() < 1e-9);
+        assert_eq!(changes[2].engine, "wamr");
+        assert_eq!((changes[2].current, changes[2].failed), (None, true));
+        assert_eq!(changes[3].engine, "wasmer");
+        assert_eq!((changes[3].current, changes[3].failed), (None, false));
+
+        assert!(!write(&mut io::sink(), &changes[..2], 10.0).unwrap());
+        assert!(write(&mut io::sink(), &changes, 10.0).unwrap());
+    }
+}
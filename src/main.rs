@@ -1,41 +1,352 @@
-use coremark_wamr::wamr_container;
-use coremark_wasm3::wasm3_container;
-use coremark_wasmedge::wasmedge_container;
-use coremark_wasmer::wasmer_container;
-use coremark_wasmi::wasmi_container;
-use coremark_wasmtime::wasmtime_container;
+use std::error::Error;
+use std::fs;
+use std::io::{self, Write};
+use std::process;
+use std::result::Result;
+
+use clap::Parser;
+use coremark_engine::{Engine, Registry};
+
+mod bench;
+mod cli;
+mod compare;
+mod report;
+mod stats;
+mod validate;
+mod worker;
+
+use bench::Fault;
+use cli::{Args, Format};
+use report::{Config, EngineReport, Failure, Report};
+
+/// Module name standing for the coremark_wasm build embedded in the harness.
+const EMBEDDED: &str = "embedded";
+
+const COREMARK_WASM: &[u8] = include_bytes!("../target/wasm32-unknown-unknown/release/coremark_wasm.wasm");
+
+// Registers `$engine` when its cargo features are enabled, otherwise lists it as unavailable. With
+// `try`, `$engine` is a `Result` whose error says why the engine cannot run on this host.
+macro_rules! register {
+    ($registry:ident, $name:literal, [$($feature:literal),+], try $engine:expr) => {
+        #[cfg(all($(feature = $feature),+))]
+        $registry.try_register($name, $engine);
+        #[cfg(not(all($(feature = $feature),+)))]
+        register!(@unavailable $registry, $name, [$($feature),+]);
+    };
+    ($registry:ident, $name:literal, [$($feature:literal),+], $engine:expr) => {
+        #[cfg(all($(feature = $feature),+))]
+        $registry.register($engine);
+        #[cfg(not(all($(feature = $feature),+)))]
+        register!(@unavailable $registry, $name, [$($feature),+]);
+    };
+    ($registry:ident, $feature:literal, $engine:expr) => {
+        register!($registry, $feature, [$feature], $engine)
+    };
+    (@unavailable $registry:ident, $name:literal, [$($feature:literal),+]) => {{
+        let features: &[&str] = &[$($feature),+];
+        let features: Vec<String> = features.iter().map(|feature| format!("`{}`", feature)).collect();
+        let plural = if features.len() > 1 { "s" } else { "" };
+        let reason = format!("not compiled in, enable the {} feature{}", features.join(" and "), plural);
+        $registry.unavailable($name, reason);
+    }};
+}
+
+#[cfg_attr(not(any(feature = "wasmtime", feature = "wasmi")), allow(unused_variables))]
+fn registry(args: &Args) -> Registry {
+    let mut registry = Registry::new();
+    // Epoch checks slow the guest down, so they are only compiled in when a timeout needs them.
+    #[cfg(feature = "wasmtime")]
+    let wasmtime = |preset| coremark_wasmtime::WasmtimeEngine::with_preset(preset, args.timeout.is_some());
+    register!(registry, "wasmtime", wasmtime(coremark_wasmtime::Preset::Default));
+    register!(registry, "wasmtime-aot", ["wasmtime", "aot"], wasmtime(coremark_wasmtime::Preset::Default).aot());
+    register!(registry, "wasmtime-opt-none", ["wasmtime-configs"], wasmtime(coremark_wasmtime::Preset::OptNone));
+    register!(registry, "wasmtime-opt-size", ["wasmtime-configs"], wasmtime(coremark_wasmtime::Preset::OptSize));
+    register!(registry, "wasmtime-winch", ["wasmtime-configs"], wasmtime(coremark_wasmtime::Preset::Winch));
+    register!(registry, "wasmtime-bounds-checks", ["wasmtime-configs"], wasmtime(coremark_wasmtime::Preset::BoundsChecks));
+    register!(registry, "wasmtime-serial-compilation", ["wasmtime-configs"], wasmtime(coremark_wasmtime::Preset::SerialCompilation));
+    register!(registry, "wasmtime-pooling", ["wasmtime-configs"], wasmtime(coremark_wasmtime::Preset::Pooling));
+    register!(registry, "wasmedge", coremark_wasmedge::WasmedgeEngine::new());
+    #[cfg(all(feature = "wasmedge", any(feature = "aot", feature = "wasmedge-configs")))]
+    let wasmedge_aot = |level| coremark_wasmedge::WasmedgeEngine::new().aot(level);
+    // The O3 row belongs to both `aot` and `wasmedge-configs`, which the macro's feature list cannot express.
+    #[cfg(all(feature = "wasmedge", any(feature = "aot", feature = "wasmedge-configs")))]
+    registry.register(wasmedge_aot(coremark_wasmedge::CompilerOptimizationLevel::O3));
+    #[cfg(not(all(feature = "wasmedge", any(feature = "aot", feature = "wasmedge-configs"))))]
+    register!(@unavailable registry, "wasmedge-aot", ["wasmedge", "aot"]);
+    register!(registry, "wasmedge-aot-o0", ["wasmedge-configs"], wasmedge_aot(coremark_wasmedge::CompilerOptimizationLevel::O0));
+    register!(registry, "wasmedge-aot-o1", ["wasmedge-configs"], wasmedge_aot(coremark_wasmedge::CompilerOptimizationLevel::O1));
+    register!(registry, "wasmedge-aot-o2", ["wasmedge-configs"], wasmedge_aot(coremark_wasmedge::CompilerOptimizationLevel::O2));
+    register!(registry, "wasmedge-aot-os", ["wasmedge-configs"], wasmedge_aot(coremark_wasmedge::CompilerOptimizationLevel::Os));
+    register!(registry, "wasmedge-aot-oz", ["wasmedge-configs"], wasmedge_aot(coremark_wasmedge::CompilerOptimizationLevel::Oz));
+    register!(registry, "wasmer", coremark_wasmer::WasmerEngine::new());
+    register!(registry, "wasmer-aot", ["wasmer", "aot"], coremark_wasmer::WasmerEngine::new().aot());
+    register!(registry, "wasmer-singlepass", coremark_wasmer::WasmerEngine::with_compiler(coremark_wasmer::Compiler::Singlepass));
+    register!(registry, "wasmer-llvm", coremark_wasmer::WasmerEngine::with_compiler(coremark_wasmer::Compiler::Llvm));
+    register!(registry, "wasm3", coremark_wasm3::Wasm3Engine::new());
+    // Fuel metering likewise slows wasmi down, so it is only enabled for a timeout.
+    #[cfg(feature = "wasmi")]
+    let wasmi = |mode, fuel| coremark_wasmi::WasmiEngine::with_mode(mode, fuel, args.timeout.is_some());
+    register!(registry, "wasmi", wasmi(coremark_wasmi::CompilationMode::Eager, false));
+    register!(registry, "wasmi-lazy-translation", ["wasmi-configs"], wasmi(coremark_wasmi::CompilationMode::LazyTranslation, false));
+    register!(registry, "wasmi-lazy", ["wasmi-configs"], wasmi(coremark_wasmi::CompilationMode::Lazy, false));
+    register!(registry, "wasmi-fuel", ["wasmi-configs"], wasmi(coremark_wasmi::CompilationMode::Eager, true));
+    register!(registry, "wasmi-lazy-translation-fuel", ["wasmi-configs"], wasmi(coremark_wasmi::CompilationMode::LazyTranslation, true));
+    register!(registry, "wasmi-lazy-fuel", ["wasmi-configs"], wasmi(coremark_wasmi::CompilationMode::Lazy, true));
+    register!(registry, "wamr", coremark_wamr::WamrEngine::new());
+    register!(registry, "wamr-fast-jit", ["wamr-configs"], try coremark_wamr::WamrEngine::with_mode(coremark_wamr::RunningMode::FastJit));
+    register!(registry, "wamr-llvm-jit", ["wamr-configs"], try coremark_wamr::WamrEngine::with_mode(coremark_wamr::RunningMode::LlvmJit));
+    register!(registry, "wamr-aot", ["wamr", "aot"], try coremark_wamr::WamrEngine::with_mode(coremark_wamr::RunningMode::Aot));
+    registry
+}
+
+fn select<'r>(registry: &'r Registry, names: &[String]) -> Result<Vec<&'r dyn Engine>, Box<dyn Error>> {
+    if names.is_empty() {
+        return Ok(registry.iter().collect());
+    }
+
+    names
+        .iter()
+        .map(|name| {
+            registry.get(name).ok_or_else(|| {
+                if let Some(reason) = registry.unavailable_reason(name) {
+                    return format!("engine `{}` is unavailable: {}", name, reason).into();
+                }
+
+                let available: Vec<&str> = registry.iter().map(|engine| engine.name()).collect();
+                format!("unknown engine `{}`, available: {}", name, available.join(", ")).into()
+            })
+        })
+        .collect()
+}
 
 fn main() {
-    let coremark_wasm = include_bytes!("../target/wasm32-unknown-unknown/release/coremark_wasm.wasm");
-    // let coremark_wasm = include_bytes!("../coremark-minimal.wasm");
-
-    println!("Running Coremark tests... [should take 12..20 seconds per engine]");
-
-    let engines = ["wasmtime", "wasmedge", "wasmer", "wasm3", "wasmi", "wamr"];
-    let mut results = vec![];
-
-    for &engine in &engines {
-        let result = match engine {
-            "wasmtime" => wasmtime_container(coremark_wasm),
-            "wasmedge" => wasmedge_container(coremark_wasm),
-            "wasmer" => wasmer_container(coremark_wasm),
-            "wasm3" => wasm3_container(coremark_wasm),
-            "wasmi" => wasmi_container(coremark_wasm),
-            "wamr" => wamr_container(coremark_wasm),
-            _ => unreachable!(),
+    let args = Args::parse();
+    let registry = registry(&args);
+
+    if args.list {
+        for engine in registry.iter() {
+            println!("{:<10} {}", engine.name(), engine.version());
+        }
+        for (name, reason) in registry.iter_unavailable() {
+            println!("{:<10} unavailable ({})", name, reason);
+        }
+        return;
+    }
+
+    let engines = select(&registry, &args.engine).unwrap_or_else(|e| {
+        eprintln!("Error: {}", e);
+        process::exit(2);
+    });
+
+    let baseline = args.compare.as_ref().map(|path| {
+        compare::load(path).unwrap_or_else(|e| {
+            eprintln!("Error: failed to load baseline {}: {}", path.display(), e);
+            process::exit(2);
+        })
+    });
+
+    let modules: Vec<(String, Vec<u8>)> = if args.module.is_empty() {
+        vec![(EMBEDDED.to_string(), COREMARK_WASM.to_vec())]
+    } else {
+        args.module
+            .iter()
+            .map(|path| {
+                let wasm = fs::read(path).unwrap_or_else(|e| {
+                    eprintln!("Error: failed to read {}: {}", path.display(), e);
+                    process::exit(2);
+                });
+                (path.display().to_string(), wasm)
+            })
+            .collect()
+    };
+
+    let options = bench::Options {
+        iterations: args.iterations,
+        target: args.target_time,
+        seeds: args.seeds,
+        warmup: args.warmup,
+        repetitions: args.repetitions,
+        timeout: args.timeout,
+    };
+
+    if args.worker {
+        let (engine, (_, wasm)) = match (engines.as_slice(), modules.as_slice()) {
+            ([engine], [module]) => (*engine, module),
+            _ => {
+                eprintln!("Error: a worker runs exactly one engine on one module");
+                process::exit(2);
+            }
+        };
+
+        worker::reply(&bench::bench(engine, wasm, &options));
+        return;
+    }
+
+    let iterations = match args.target_time {
+        Some(target) => format!("iterations calibrated to {:.1}s", target.as_secs_f64()),
+        None => format!("{} iterations", args.iterations),
+    };
+    eprintln!("Running Coremark tests... [{}, {} runs per engine]", iterations, args.repetitions);
+
+    let mut report = Report {
+        schema: report::SCHEMA,
+        config: Config {
+            modules: modules.iter().map(|(name, _)| name.clone()).collect(),
+            iterations: args.iterations,
+            target_ms: args.target_time.map(|target| target.as_secs_f64() * 1000.0),
+            seeds: args.seeds,
+            warmup: args.warmup,
+            repetitions: args.repetitions,
+            reject_outliers: args.reject_outliers,
+            max_divergence: args.max_divergence,
+            cpu_mhz: args.cpu_mhz,
+        },
+        engines: vec![],
+        failures: vec![],
+    };
+
+    for (module, wasm) in &modules {
+        for &engine in &engines {
+            let interruptible = engine.capabilities().interruptible;
+            let outcome = if args.isolate || (args.timeout.is_some() && !interruptible) {
+                worker::spawn(engine.name(), module, &options)
+            } else {
+                bench::bench(engine, wasm, &options)
+            };
+
+            match outcome {
+                Ok(samples) => {
+                    let rejected = if args.reject_outliers {
+                        let results: Vec<f64> = samples.iter().map(|s| s.result as f64).collect();
+                        stats::outliers(&results)
+                    } else {
+                        vec![]
+                    };
+
+                    let diverged = bench::diverged(&samples, args.max_divergence);
+                    if !diverged.is_empty() {
+                        let worst = diverged.iter().map(|&i| samples[i].divergence()).fold(0.0, f64::max);
+                        eprintln!(
+                            "Warning: {} running {}: guest and host times differ by up to {:.1}% in {} of {} runs",
+                            engine.name(),
+                            module,
+                            worst,
+                            diverged.len(),
+                            samples.len()
+                        );
+                    }
+
+                    let mut row = EngineReport::new(
+                        engine.name(),
+                        engine.version(),
+                        module,
+                        &report.config,
+                        samples,
+                        rejected,
+                        diverged,
+                    );
+                    for error in &row.crc_errors {
+                        eprintln!("Error: {} running {} computed a wrong result: {}", engine.name(), module, error);
+                    }
+
+                    if args.threads > 1 {
+                        let capabilities = engine.capabilities();
+                        if !capabilities.shared_module {
+                            eprintln!("Warning: {} cannot share a module across threads, skipping --threads", engine.name());
+                        } else if args.timeout.is_some() && !capabilities.interruptible {
+                            eprintln!("Warning: {} cannot be interrupted on a timeout, skipping --threads", engine.name());
+                        } else {
+                            match bench::parallel(engine, wasm, &options, args.threads, row.iterations()) {
+                                Ok(runs) => {
+                                    row.parallel = Some(report::Parallel::new(
+                                        args.threads,
+                                        row.iterations(),
+                                        runs,
+                                        row.summary.score.median,
+                                    ));
+                                }
+                                Err(fault) => {
+                                    let error = format!("with {} threads: {}", args.threads, fault);
+                                    eprintln!("Error occurred in {} running {}: {}", engine.name(), module, error);
+                                    report.failures.push(Failure {
+                                        engine: engine.name().to_string(),
+                                        module: module.clone(),
+                                        error,
+                                        timed_out: matches!(fault, Fault::Timeout),
+                                    });
+                                }
+                            }
+                        }
+                    }
+
+                    report.engines.push(row);
+                }
+                Err(fault) => {
+                    let timed_out = matches!(fault, Fault::Timeout);
+                    let error = match (&fault, args.timeout) {
+                        (Fault::Timeout, Some(timeout)) => format!("timed out after {:.1}s", timeout.as_secs_f64()),
+                        _ => fault.to_string(),
+                    };
+
+                    eprintln!("Error occurred in {} running {}: {}", engine.name(), module, error);
+                    report.failures.push(Failure {
+                        engine: engine.name().to_string(),
+                        module: module.clone(),
+                        error,
+                        timed_out,
+                    });
+                }
+            }
+        }
+
+        let samples: Vec<&[bench::Sample]> = report
+            .engines
+            .iter()
+            .filter(|row| &row.module == module)
+            .map(|row| row.samples.as_slice())
+            .collect();
+        let disagreements = validate::differential(&samples);
+        for (row, found) in report.engines.iter_mut().filter(|row| &row.module == module).zip(disagreements) {
+            for error in &found {
+                eprintln!("Error: {} running {} disagrees with the other engines: {}", row.engine, module, error);
+            }
+            row.disagreements = found;
+        }
+    }
+
+    report::print(&report, args.format);
+    let invalid = report
+        .engines
+        .iter()
+        .any(|row| !row.crc_errors.is_empty() || !row.disagreements.is_empty());
+
+    if let Some(path) = &args.output {
+        let json = serde_json::to_string_pretty(&report).expect("report serializes to JSON");
+        if let Err(e) = fs::write(path, json) {
+            eprintln!("Error: failed to write {}: {}", path.display(), e);
+            process::exit(2);
+        }
+    }
+
+    if let Some(baseline) = baseline {
+        // Keep stdout parseable when it carries a machine-readable report.
+        let mut out: Box<dyn Write> = match args.format {
+            Format::Json | Format::Csv => Box::new(io::stderr()),
+            Format::Markdown | Format::Text => Box::new(io::stdout()),
         };
 
-        match result {
-            Ok(value) => results.push((engine, value)),
+        let changes = compare::changes(&baseline, &report);
+        match compare::write(&mut out, &changes, args.threshold) {
+            Ok(false) => {}
+            Ok(true) => process::exit(1),
             Err(e) => {
-                eprintln!("Error occurred: {}", e);
+                eprintln!("Error: failed to write comparison: {}", e);
+                process::exit(2);
             }
         }
     }
 
-    println!("\nResults:\n");
-    println!("| Engine     | Result(ms)         |\n|------------|--------------------|");
-    for (engine, result) in results {
-        println!("| {:<10} | {:<18.2} |", engine, result);
+    if invalid {
+        process::exit(1);
     }
 }
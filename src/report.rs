@@ -0,0 +1,468 @@
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::bench::{ParallelRun, Sample};
+use crate::cli::Format;
+use crate::stats::{self, Summary};
+use crate::validate;
+
+/// Bumped whenever a field of the JSON report changes meaning or is removed.
+pub const SCHEMA: u32 = 3;
+
+/// Shortest guest-reported time of a run that CoreMark accepts as a valid result.
+pub const MIN_VALID_MS: f64 = 10_000.0;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Report {
+    pub schema: u32,
+    pub config: Config,
+    pub engines: Vec<EngineReport>,
+    #[serde(default)]
+    pub failures: Vec<Failure>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Config {
+    /// Paths of the guest modules, or `embedded` for the bundled coremark_wasm build.
+    pub modules: Vec<String>,
+    /// Iterations of each run, unless calibrated per engine to `target_ms`.
+    pub iterations: u32,
+    #[serde(default)]
+    pub target_ms: Option<f64>,
+    #[serde(default = "default_seeds")]
+    pub seeds: [i32; 3],
+    pub warmup: u32,
+    pub repetitions: u32,
+    pub reject_outliers: bool,
+    /// Percent by which guest and host times may differ before a run is flagged as diverged.
+    #[serde(default)]
+    pub max_divergence: f64,
+    /// CPU frequency given with `--cpu-mhz`, used for the CoreMark/MHz summary.
+    pub cpu_mhz: Option<f64>,
+}
+
+fn default_seeds() -> [i32; 3] {
+    validate::PERFORMANCE_SEEDS
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EngineReport {
+    pub engine: String,
+    pub version: String,
+    pub module: String,
+    pub samples: Vec<Sample>,
+    /// Indices of samples excluded from the summary as outliers.
+    pub rejected: Vec<usize>,
+    /// Indices of samples whose guest-reported time disagrees with the host-timed call.
+    #[serde(default)]
+    pub diverged: Vec<usize>,
+    /// CRCs that differ from the known-good values, meaning the engine computed a wrong result.
+    #[serde(default)]
+    pub crc_errors: Vec<String>,
+    /// Outputs that differ from the majority of the engines that ran the same module.
+    #[serde(default)]
+    pub disagreements: Vec<String>,
+    /// Indices of samples shorter than `MIN_VALID_MS`, which CoreMark does not accept as valid.
+    #[serde(default)]
+    pub short: Vec<usize>,
+    pub summary: Summaries,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parallel: Option<Parallel>,
+}
+
+/// Runs with `--threads`, each thread running its own instance of one shared module.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Parallel {
+    pub threads: u32,
+    pub iterations: u32,
+    pub runs: Vec<ParallelRun>,
+    /// Iterations per second of all threads together, over the guest-reported time of the slowest
+    /// thread, the same clock as the single-threaded score.
+    pub score: Summary,
+    /// Aggregate score per thread relative to the single-threaded median score.
+    pub efficiency: Summary,
+}
+
+impl Parallel {
+    pub fn new(threads: u32, iterations: u32, runs: Vec<ParallelRun>, single_score: f64) -> Self {
+        let scores: Vec<f64> = runs
+            .iter()
+            .map(|run| threads as f64 * score(iterations, run.results.iter().fold(0.0, |slowest, &ms| ms.max(slowest))))
+            .collect();
+        let efficiency: Vec<f64> = scores
+            .iter()
+            .map(|score| score / threads as f64 / single_score)
+            .collect();
+
+        Self {
+            threads,
+            iterations,
+            runs,
+            score: stats::summarize(&scores).expect("at least one parallel run"),
+            efficiency: stats::summarize(&efficiency).expect("at least one parallel run"),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Failure {
+    pub engine: String,
+    pub module: String,
+    pub error: String,
+    #[serde(default)]
+    pub timed_out: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Summaries {
+    pub result_ms: Summary,
+    /// CoreMark score, iterations per second of guest-reported time.
+    pub score: Summary,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub score_per_mhz: Option<Summary>,
+    /// Ahead-of-time compilation, for engines that precompile the module.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub precompile_ms: Option<Summary>,
+    pub compile_ms: Summary,
+    pub instantiate_ms: Summary,
+    pub warmup_ms: Summary,
+    pub execute_ms: Summary,
+}
+
+impl EngineReport {
+    pub fn new(
+        engine: &str,
+        version: &str,
+        module: &str,
+        config: &Config,
+        samples: Vec<Sample>,
+        rejected: Vec<usize>,
+        diverged: Vec<usize>,
+    ) -> Self {
+        let summary = |value: &dyn Fn(&Sample) -> f64| {
+            let values: Vec<f64> = samples
+                .iter()
+                .enumerate()
+                .filter(|(i, _)| !rejected.contains(i))
+                .map(|(_, sample)| value(sample))
+                .collect();
+
+            stats::summarize(&values).expect("at least one sample is kept")
+        };
+
+        let crc_errors = validate::check(&samples, config.seeds);
+
+        let short = samples
+            .iter()
+            .enumerate()
+            .filter(|(_, sample)| (sample.result as f64) < MIN_VALID_MS)
+            .map(|(i, _)| i)
+            .collect();
+
+        let score = |s: &Sample| score(s.iterations, s.result);
+        let summary = Summaries {
+            result_ms: summary(&|s| s.result as f64),
+            score: summary(&score),
+            score_per_mhz: config.cpu_mhz.map(|mhz| summary(&|s| score(s) / mhz)),
+            precompile_ms: samples
+                .iter()
+                .any(|s| !s.precompile.is_zero())
+                .then(|| summary(&|s| ms(s.precompile))),
+            compile_ms: summary(&|s| ms(s.compile)),
+            instantiate_ms: summary(&|s| ms(s.instantiate)),
+            warmup_ms: summary(&|s| ms(s.warmup.iter().sum())),
+            execute_ms: summary(&|s| ms(s.execute)),
+        };
+
+        Self {
+            engine: engine.to_string(),
+            version: version.to_string(),
+            module: module.to_string(),
+            samples,
+            rejected,
+            diverged,
+            crc_errors,
+            disagreements: vec![],
+            short,
+            summary,
+            parallel: None,
+        }
+    }
+
+    pub fn iterations(&self) -> u32 {
+        self.samples.first().map_or(0, |sample| sample.iterations)
+    }
+
+    fn runs(&self) -> String {
+        let mut notes = vec![];
+        if !self.rejected.is_empty() {
+            notes.push(format!("{} rejected", self.rejected.len()));
+        }
+        if !self.diverged.is_empty() {
+            notes.push(format!("{} diverged", self.diverged.len()));
+        }
+        if !self.short.is_empty() {
+            notes.push(format!("{} short", self.short.len()));
+        }
+
+        match notes.is_empty() {
+            true => self.samples.len().to_string(),
+            false => format!("{} ({})", self.samples.len(), notes.join(", ")),
+        }
+    }
+}
+
+fn ms(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+/// Iterations per second, given the guest-reported time of one `run` call.
+fn score(iterations: u32, result_ms: f32) -> f64 {
+    if result_ms <= 0.0 {
+        return 0.0;
+    }
+    iterations as f64 / (result_ms as f64 / 1000.0)
+}
+
+pub fn print(report: &Report, format: Format) {
+    match format {
+        Format::Markdown => print_markdown(report),
+        Format::Text => print_text(report),
+        Format::Json => {
+            println!("{}", serde_json::to_string_pretty(report).expect("report serializes to JSON"));
+        }
+        Format::Csv => print_csv(report),
+    }
+}
+
+fn print_markdown(report: &Report) {
+    for module in &report.config.modules {
+        if report.config.modules.len() > 1 {
+            println!("\nResults for {}:\n", module);
+        } else {
+            println!("\nResults:\n");
+        }
+        print_markdown_table(
+            report.engines.iter().filter(|row| &row.module == module),
+            report.failures.iter().filter(|failure| &failure.module == module),
+        );
+        print_markdown_parallel(report.engines.iter().filter(|row| &row.module == module));
+    }
+}
+
+fn print_markdown_table<'a>(
+    rows: impl Iterator<Item = &'a EngineReport>,
+    failures: impl Iterator<Item = &'a Failure>,
+) {
+    println!(
+        "| Engine     | Result(ms)   | Score(it/s)  | CoreMark/MHz | Min(ms)    | Mean(ms)   | Stddev(ms) | CI95(ms)   | Precompile(ms) | Compile(ms)  | Instantiate(ms) | Warmup(ms)   | Execute(ms)  | Runs           |\n\
+         |------------|--------------|--------------|--------------|------------|------------|------------|------------|----------------|--------------|-----------------|--------------|--------------|----------------|"
+    );
+    let mut crc_errors = vec![];
+    let mut disagreements = vec![];
+    for row in rows {
+        crc_errors.extend(row.crc_errors.iter().map(|error| (&row.engine, error)));
+        disagreements.extend(row.disagreements.iter().map(|error| (&row.engine, error)));
+
+        let summary = &row.summary;
+        println!(
+            "| {:<10} | {:<12.2} | {:<12.2} | {:<12} | {:<10.2} | {:<10.2} | {:<10.2} | {:<10} | {:<14} | {:<12.2} | {:<15.2} | {:<12.2} | {:<12.2} | {:<14} |",
+            row.engine,
+            summary.result_ms.median,
+            summary.score.median,
+            summary.score_per_mhz.map_or_else(|| "-".to_string(), |per_mhz| format!("{:.2}", per_mhz.median)),
+            summary.result_ms.min,
+            summary.result_ms.mean,
+            summary.result_ms.stddev,
+            format!("±{:.2}", summary.result_ms.ci95),
+            summary.precompile_ms.map_or_else(|| "-".to_string(), |precompile| format!("{:.2}", precompile.median)),
+            summary.compile_ms.median,
+            summary.instantiate_ms.median,
+            summary.warmup_ms.median,
+            summary.execute_ms.median,
+            row.runs(),
+        );
+    }
+    for failure in failures {
+        let label = if failure.timed_out { "" } else { "error: " };
+        println!("| {:<10} | {}{} |{}", failure.engine, label, failure.error, "   |".repeat(12));
+    }
+    for (engine, error) in crc_errors {
+        println!("| {:<10} | CRC mismatch in {} |{}", engine, error, "   |".repeat(12));
+    }
+    for (engine, error) in disagreements {
+        println!("| {:<10} | disagrees with other engines: {} |{}", engine, error, "   |".repeat(12));
+    }
+}
+
+fn print_markdown_parallel<'a>(rows: impl Iterator<Item = &'a EngineReport>) {
+    let mut rows = rows.filter_map(|row| Some((row, row.parallel.as_ref()?))).peekable();
+    if rows.peek().is_none() {
+        return;
+    }
+
+    println!(
+        "\n| Engine     | Threads  | Aggregate(it/s) | Per thread(it/s) | Single(it/s) | Efficiency |\n\
+         |------------|----------|-----------------|------------------|--------------|------------|"
+    );
+    for (row, parallel) in rows {
+        println!(
+            "| {:<10} | {:<8} | {:<15.2} | {:<16.2} | {:<12.2} | {:<10} |",
+            row.engine,
+            parallel.threads,
+            parallel.score.median,
+            parallel.score.median / parallel.threads as f64,
+            row.summary.score.median,
+            format!("{:.1}%", parallel.efficiency.median * 100.0),
+        );
+    }
+}
+
+fn print_text(report: &Report) {
+    for row in &report.engines {
+        let summary = &row.summary;
+        let name = if report.config.modules.len() > 1 {
+            format!("{} [{}]", row.engine, row.module)
+        } else {
+            row.engine.clone()
+        };
+        println!(
+            "{}: {:.2} ±{:.2} ms, {:.2} iterations/s over {} runs of {} iterations (min {:.2}, mean {:.2}, stddev {:.2}; {}compile {:.2} ms, instantiate {:.2} ms, execute {:.2} ms)",
+            name,
+            summary.result_ms.median,
+            summary.result_ms.ci95,
+            summary.score.median,
+            row.runs(),
+            row.iterations(),
+            summary.result_ms.min,
+            summary.result_ms.mean,
+            summary.result_ms.stddev,
+            summary
+                .precompile_ms
+                .map_or_else(String::new, |precompile| format!("precompile {:.2} ms, ", precompile.median)),
+            summary.compile_ms.median,
+            summary.instantiate_ms.median,
+            summary.execute_ms.median,
+        );
+        if report.config.warmup > 0 {
+            let first: Vec<f64> = row.samples.iter().map(|s| ms(s.warmup[0])).collect();
+            println!(
+                "  warmup: {} calls, {:.2} ms total, first call {:.2} ms",
+                report.config.warmup,
+                summary.warmup_ms.median,
+                stats::summarize(&first).expect("at least one sample").median,
+            );
+        }
+        if let Some(parallel) = &row.parallel {
+            println!(
+                "  {} threads: {:.2} iterations/s aggregate, {:.1}% scaling efficiency",
+                parallel.threads,
+                parallel.score.median,
+                parallel.efficiency.median * 100.0,
+            );
+        }
+        for error in &row.crc_errors {
+            println!("  CRC mismatch in {}", error);
+        }
+        for error in &row.disagreements {
+            println!("  disagrees with other engines: {}", error);
+        }
+    }
+    for failure in &report.failures {
+        let label = if failure.timed_out { "" } else { "error: " };
+        if report.config.modules.len() > 1 {
+            println!("{} [{}]: {}{}", failure.engine, failure.module, label, failure.error);
+        } else {
+            println!("{}: {}{}", failure.engine, label, failure.error);
+        }
+    }
+    for row in &report.engines {
+        print_coremark_summary(&report.config, row);
+    }
+}
+
+// Mirrors the report printed at the end of a native CoreMark run, from the median of the runs.
+fn print_coremark_summary(config: &Config, row: &EngineReport) {
+    let summary = &row.summary;
+    let runtime = format!("{} {}", row.engine, row.version);
+
+    println!();
+    if config.modules.len() > 1 {
+        println!("Module           : {}", row.module);
+    }
+    println!("Total ticks      : {:.0}", summary.result_ms.median * 1_000_000.0);
+    println!("Total time (secs): {:.6}", summary.result_ms.median / 1000.0);
+    println!("Iterations/Sec   : {:.6}", summary.score.median);
+    println!("Iterations       : {}", row.iterations());
+    println!("Seeds            : {:#x}, {:#x}, {:#x}", config.seeds[0], config.seeds[1], config.seeds[2]);
+    println!("Compiler version : {}", runtime);
+    if let Some(per_mhz) = summary.score_per_mhz {
+        println!("CoreMark/MHz     : {:.6}", per_mhz.median);
+    }
+    let sample = row.samples.first();
+    if let Some(kernels) = sample.and_then(|sample| sample.kernel_crcs) {
+        println!("crclist          : {:#06x}", kernels.list);
+        println!("crcmatrix        : {:#06x}", kernels.matrix);
+        println!("crcstate         : {:#06x}", kernels.state);
+    }
+    match sample.and_then(|sample| sample.crc) {
+        Some(crc) => println!("crcfinal         : {:#06x}", crc),
+        None => println!("crcfinal         : (not exported)"),
+    }
+    let exported = sample.is_some_and(|sample| sample.kernel_crcs.is_some());
+    let correct = row.crc_errors.is_empty() && row.disagreements.is_empty();
+    match (exported, validate::expected(config.seeds), correct) {
+        (_, _, false) => println!("Errors detected"),
+        (true, Some(_), true) => println!("Correct operation validated."),
+        (false, _, true) => println!("Cannot validate operation, the module does not export kernel CRCs"),
+        (true, None, true) => println!("Cannot validate operation, no known-good CRCs for these seeds"),
+    }
+    if summary.result_ms.median < MIN_VALID_MS {
+        println!("ERROR! Must execute for at least 10 secs for a valid result!");
+    }
+    println!("CoreMark 1.0 : {:.6} / {}", summary.score.median, runtime);
+}
+
+fn print_csv(report: &Report) {
+    println!("engine,version,module,run,iterations,result_ms,score,precompile_ms,compile_ms,instantiate_ms,warmup_ms,execute_ms,crc,crc_list,crc_matrix,crc_state,rejected,diverged,short,error");
+    for row in &report.engines {
+        for (i, sample) in row.samples.iter().enumerate() {
+            println!(
+                "{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},",
+                quote(&row.engine),
+                quote(&row.version),
+                quote(&row.module),
+                i,
+                sample.iterations,
+                sample.result,
+                score(sample.iterations, sample.result),
+                ms(sample.precompile),
+                ms(sample.compile),
+                ms(sample.instantiate),
+                ms(sample.warmup.iter().sum()),
+                ms(sample.execute),
+                sample.crc.map(|crc| crc.to_string()).unwrap_or_default(),
+                sample.kernel_crcs.map(|crcs| crcs.list.to_string()).unwrap_or_default(),
+                sample.kernel_crcs.map(|crcs| crcs.matrix.to_string()).unwrap_or_default(),
+                sample.kernel_crcs.map(|crcs| crcs.state.to_string()).unwrap_or_default(),
+                row.rejected.contains(&i),
+                row.diverged.contains(&i),
+                row.short.contains(&i),
+            );
+        }
+    }
+    for failure in &report.failures {
+        println!(
+            "{},,{},,,,,,,,,,,,,,,,,{}",
+            quote(&failure.engine),
+            quote(&failure.module),
+            quote(&failure.error)
+        );
+    }
+}
+
+/// Quotes a CSV field, so commas, quotes and newlines in names, paths and errors stay in one column.
+fn quote(field: &str) -> String {
+    format!("\"{}\"", field.replace('"', "\"\""))
+}
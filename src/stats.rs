@@ -0,0 +1,112 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Summary {
+    pub min: f64,
+    pub median: f64,
+    pub mean: f64,
+    pub stddev: f64,
+    /// Half-width of the 95% confidence interval of the mean.
+    pub ci95: f64,
+}
+
+// Two-sided 95% quantiles of Student's t distribution for 1..=30 degrees of freedom.
+const T95: [f64; 30] = [
+    12.706, 4.303, 3.182, 2.776, 2.571, 2.447, 2.365, 2.306, 2.262, 2.228, 2.201, 2.179, 2.160,
+    2.145, 2.131, 2.120, 2.110, 2.101, 2.093, 2.086, 2.080, 2.074, 2.069, 2.064, 2.060, 2.056,
+    2.052, 2.048, 2.045, 2.042,
+];
+
+fn t95(df: usize) -> f64 {
+    T95.get(df.wrapping_sub(1)).copied().unwrap_or(1.960)
+}
+
+fn sorted(values: &[f64]) -> Vec<f64> {
+    let mut sorted = values.to_vec();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    sorted
+}
+
+fn quantile(sorted: &[f64], q: f64) -> f64 {
+    let pos = (sorted.len() - 1) as f64 * q;
+    let (lo, hi) = (pos.floor() as usize, pos.ceil() as usize);
+    sorted[lo] + (sorted[hi] - sorted[lo]) * (pos - lo as f64)
+}
+
+pub fn summarize(values: &[f64]) -> Option<Summary> {
+    if values.is_empty() {
+        return None;
+    }
+
+    let sorted = sorted(values);
+    let n = sorted.len();
+    let mean = sorted.iter().sum::<f64>() / n as f64;
+    let stddev = if n > 1 {
+        (sorted.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / (n - 1) as f64).sqrt()
+    } else {
+        0.0
+    };
+    let ci95 = if n > 1 {
+        t95(n - 1) * stddev / (n as f64).sqrt()
+    } else {
+        0.0
+    };
+
+    Some(Summary {
+        min: sorted[0],
+        median: quantile(&sorted, 0.5),
+        mean,
+        stddev,
+        ci95,
+    })
+}
+
+/// Indices of the values outside Tukey's fences (1.5 IQR beyond the quartiles).
+pub fn outliers(values: &[f64]) -> Vec<usize> {
+    if values.len() < 4 {
+        return vec![];
+    }
+
+    let sorted = sorted(values);
+    let (q1, q3) = (quantile(&sorted, 0.25), quantile(&sorted, 0.75));
+    let iqr = q3 - q1;
+    let (lo, hi) = (q1 - 1.5 * iqr, q3 + 1.5 * iqr);
+
+    values
+        .iter()
+        .enumerate()
+        .filter(|(_, &v)| v < lo || v > hi)
+        .map(|(i, _)| i)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_summarize() {
+        let summary = summarize(&[4.0, 2.0, 3.0, 1.0, 5.0]).unwrap();
+        assert_eq!(summary.min, 1.0);
+        assert_eq!(summary.median, 3.0);
+        assert_eq!(summary.mean, 3.0);
+        assert!((summary.stddev - 2.5f64.sqrt()).abs() < 1e-9);
+        assert!((summary.ci95 - 2.776 * 2.5f64.sqrt() / 5f64.sqrt()).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_summarize_single() {
+        let summary = summarize(&[7.0]).unwrap();
+        assert_eq!(summary.median, 7.0);
+        assert_eq!(summary.stddev, 0.0);
+        assert_eq!(summary.ci95, 0.0);
+        assert_eq!(summarize(&[]), None);
+    }
+
+    #[test]
+    fn test_outliers() {
+        assert_eq!(outliers(&[10.0, 11.0, 10.5, 30.0, 10.2, 9.8]), vec![3]);
+        assert!(outliers(&[10.0, 11.0, 10.5, 10.2]).is_empty());
+        assert!(outliers(&[1.0, 100.0]).is_empty());
+    }
+}
@@ -0,0 +1,203 @@
+use crate::bench::{KernelCrcs, Sample};
+
+/// Seeds of a CoreMark performance run, used unless `--seeds` is given.
+pub const PERFORMANCE_SEEDS: [i32; 3] = [0, 0, 0x66];
+
+/// CRCs of one iteration of each coremark_wasm kernel for the seeds of CoreMark's performance,
+/// validation and profile runs, as computed natively by the guest's tests.
+const KNOWN_GOOD: [([i32; 3], KernelCrcs); 3] = [
+    (PERFORMANCE_SEEDS, KernelCrcs { list: 0xf059, matrix: 0x0f94, state: 0x4a47 }),
+    ([0x3415, 0x3415, 0x66], KernelCrcs { list: 0x51d2, matrix: 0xb04a, state: 0x4a47 }),
+    ([8, 8, 8], KernelCrcs { list: 0x9e34, matrix: 0xe4ff, state: 0x73d4 }),
+];
+
+pub fn expected(seeds: [i32; 3]) -> Option<KernelCrcs> {
+    KNOWN_GOOD
+        .iter()
+        .find(|(known, _)| *known == seeds)
+        .map(|&(_, crcs)| crcs)
+}
+
+/// Final CRC of `run(iterations)`, which folds the kernel CRCs of every iteration.
+pub fn final_crc(kernels: &KernelCrcs, iterations: u32) -> u16 {
+    let step = |crc| {
+        let crc = crc16(&kernels.list.to_le_bytes(), crc);
+        let crc = crc16(&kernels.matrix.to_le_bytes(), crc);
+        crc16(&kernels.state.to_le_bytes(), crc)
+    };
+
+    // Each CRC only depends on the previous one, so the sequence repeats within 2^16 iterations
+    // and whole periods can be skipped for calibrated runs of millions of iterations.
+    let mut seen = vec![u32::MAX; 1 << 16];
+    let mut crc = 0;
+    for i in 0..iterations {
+        if seen[crc as usize] != u32::MAX {
+            let period = i - seen[crc as usize];
+            return (0..(iterations - i) % period).fold(crc, |crc, _| step(crc));
+        }
+        seen[crc as usize] = i;
+        crc = step(crc);
+    }
+    crc
+}
+
+/// Describes each CRC of `samples` that differs from the known-good value. Modules that do not
+/// export the kernel CRCs, and seeds without known-good values, are not validated.
+pub fn check(samples: &[Sample], seeds: [i32; 3]) -> Vec<String> {
+    let Some(expected) = expected(seeds) else {
+        return vec![];
+    };
+
+    let mut errors = vec![];
+    for (i, sample) in samples.iter().enumerate() {
+        let Some(kernels) = sample.kernel_crcs else {
+            continue;
+        };
+
+        let expected_final = final_crc(&expected, sample.iterations);
+        for (name, actual, expected) in [
+            ("crclist", Some(kernels.list), expected.list),
+            ("crcmatrix", Some(kernels.matrix), expected.matrix),
+            ("crcstate", Some(kernels.state), expected.state),
+            ("crcfinal", sample.crc, expected_final),
+        ] {
+            if let Some(actual) = actual.filter(|&actual| actual != expected) {
+                errors.push(format!("run {}: {} {:#06x}, expected {:#06x}", i, name, actual, expected));
+            }
+        }
+    }
+    errors
+}
+
+type Output = fn(&Sample) -> Option<u64>;
+
+// Outputs compared across engines, and whether they depend on the iteration count.
+const OUTPUTS: [(&str, bool, Output); 5] = [
+    ("crclist", false, |sample| sample.kernel_crcs.map(|crcs| crcs.list.into())),
+    ("crcmatrix", false, |sample| sample.kernel_crcs.map(|crcs| crcs.matrix.into())),
+    ("crcstate", false, |sample| sample.kernel_crcs.map(|crcs| crcs.state.into())),
+    ("crcfinal", true, |sample| sample.crc.map(u64::from)),
+    ("memory digest", true, |sample| sample.memory_digest),
+];
+
+/// Compares the outputs of the engines that ran one module, given the samples of each engine,
+/// and describes for each engine where it disagrees with the majority. Outputs that depend on
+/// the iteration count are only compared between engines that ran as many iterations.
+pub fn differential(engines: &[&[Sample]]) -> Vec<Vec<String>> {
+    let mut disagreements = vec![vec![]; engines.len()];
+
+    for (samples, found) in engines.iter().zip(&mut disagreements) {
+        let differs = OUTPUTS.iter().any(|(_, _, output)| {
+            samples.windows(2).any(|pair| output(&pair[0]) != output(&pair[1]))
+        });
+        if differs {
+            found.push("outputs differ between its own runs".to_string());
+        }
+    }
+
+    for (name, per_iteration, output) in OUTPUTS {
+        let values: Vec<(usize, u32, u64)> = engines
+            .iter()
+            .enumerate()
+            .filter_map(|(i, samples)| {
+                let sample = samples.first()?;
+                let group = if per_iteration { sample.iterations } else { 0 };
+                Some((i, group, output(sample)?))
+            })
+            .collect();
+
+        let mut groups: Vec<u32> = values.iter().map(|&(_, group, _)| group).collect();
+        groups.sort_unstable();
+        groups.dedup();
+
+        for group in groups {
+            let members: Vec<(usize, u64)> = values
+                .iter()
+                .filter(|&&(_, g, _)| g == group)
+                .map(|&(i, _, value)| (i, value))
+                .collect();
+            let count = |value: u64| members.iter().filter(|&&(_, v)| v == value).count();
+            let Some(majority) = members.iter().map(|&(_, value)| value).max_by_key(|&value| count(value)) else {
+                continue;
+            };
+            if count(majority) == members.len() {
+                continue;
+            }
+
+            let n = members.len();
+            for &(i, value) in &members {
+                if count(majority) * 2 <= n {
+                    disagreements[i].push(format!("{} {:#x}, no majority among {} engines", name, value, n));
+                } else if value != majority {
+                    disagreements[i].push(format!(
+                        "{} {:#x}, {} of {} engines have {:#x}",
+                        name,
+                        value,
+                        count(majority),
+                        n,
+                        majority
+                    ));
+                }
+            }
+        }
+    }
+
+    disagreements
+}
+
+fn crc16(data: &[u8], mut crc: u16) -> u16 {
+    for &byte in data {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            if (crc & 0x8000) != 0 {
+                crc = (crc << 1) ^ 0x1021;
+            } else {
+                crc <<= 1;
+            }
+        }
+    }
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(crc: u16, memory_digest: u64) -> Sample {
+        Sample {
+            crc: Some(crc),
+            memory_digest: Some(memory_digest),
+            ..Sample::new(1.0)
+        }
+    }
+
+    #[test]
+    fn test_differential() {
+        let (good, bad) = ([sample(1, 7)], [sample(2, 7)]);
+        let found = differential(&[&good, &bad, &good]);
+        assert!(found[0].is_empty() && found[2].is_empty());
+        assert_eq!(found[1], vec!["crcfinal 0x2, 2 of 3 engines have 0x1"]);
+
+        let found = differential(&[&good, &bad]);
+        assert_eq!(found[0], vec!["crcfinal 0x1, no majority among 2 engines"]);
+
+        let mixed = [sample(1, 7), sample(1, 8)];
+        let found = differential(&[&good, &mixed]);
+        assert_eq!(found[1][0], "outputs differ between its own runs");
+    }
+
+    #[test]
+    fn test_final_crc() {
+        let kernels = expected(PERFORMANCE_SEEDS).unwrap();
+        assert_eq!(final_crc(&kernels, 0), 0);
+        assert_eq!(final_crc(&kernels, 3), 0x1649);
+
+        let folded = (0..200_000).fold(0, |crc, _| {
+            let crc = crc16(&kernels.list.to_le_bytes(), crc);
+            let crc = crc16(&kernels.matrix.to_le_bytes(), crc);
+            crc16(&kernels.state.to_le_bytes(), crc)
+        });
+        assert_eq!(final_crc(&kernels, 200_000), folded);
+        assert_eq!(expected([1, 2, 3]), None);
+    }
+}
@@ -0,0 +1,110 @@
+use std::env;
+use std::io::{self, Read, Write};
+use std::process::{Command, ExitStatus, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::bench::{Fault, Options, Sample};
+use crate::EMBEDDED;
+
+pub type Outcome = Result<Vec<Sample>, Fault>;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Re-executes the harness as a worker benchmarking one engine on one module.
+///
+/// The worker replies with a JSON encoded `Outcome` on stdout, so anything that prevents the
+/// reply (a crash, a signal, an abort) turns into an error for this engine only. A worker still
+/// running when `options.timeout` expires is killed.
+pub fn spawn(engine: &str, module: &str, options: &Options) -> Outcome {
+    let fault = |error: String| Fault::Error(error);
+
+    let exe = env::current_exe().map_err(|e| fault(format!("failed to locate harness executable: {}", e)))?;
+
+    let mut command = Command::new(exe);
+    command
+        .arg("--worker")
+        .args(["--engine", engine])
+        .arg("--seeds")
+        .arg(options.seeds.map(|seed| seed.to_string()).join(","))
+        .args(["--warmup", &options.warmup.to_string()])
+        .args(["--repetitions", &options.repetitions.to_string()])
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit());
+
+    if module != EMBEDDED {
+        command.args(["--module", module]);
+    }
+    match options.target {
+        Some(target) => command.args(["--target-time", &target.as_secs_f64().to_string()]),
+        None => command.args(["--iterations", &options.iterations.to_string()]),
+    };
+
+    let deadline = options.timeout.map(|timeout| Instant::now() + timeout);
+
+    let mut child = command
+        .spawn()
+        .map_err(|e| fault(format!("failed to start worker: {}", e)))?;
+
+    // Drain stdout while waiting, so a large reply cannot block the worker on a full pipe.
+    let mut stdout = child.stdout.take().expect("worker stdout is piped");
+    let reader = thread::spawn(move || {
+        let mut reply = vec![];
+        stdout.read_to_end(&mut reply).map(|_| reply)
+    });
+
+    let status = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break status,
+            Ok(None) if deadline.is_some_and(|deadline| Instant::now() >= deadline) => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(Fault::Timeout);
+            }
+            Ok(None) => thread::sleep(POLL_INTERVAL),
+            Err(e) => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(fault(format!("failed to wait for worker: {}", e)));
+            }
+        }
+    };
+
+    if !status.success() {
+        return Err(fault(describe(status)));
+    }
+
+    let reply = reader
+        .join()
+        .expect("worker reader does not panic")
+        .map_err(|e| fault(format!("failed to read worker reply: {}", e)))?;
+
+    serde_json::from_slice(&reply).map_err(|e| fault(format!("malformed worker reply: {}", e)))?
+}
+
+pub fn reply(outcome: &Outcome) {
+    let json = serde_json::to_string(outcome).expect("worker reply serializes to JSON");
+
+    let mut stdout = io::stdout().lock();
+    if writeln!(stdout, "{}", json).and_then(|_| stdout.flush()).is_err() {
+        std::process::exit(2);
+    }
+}
+
+fn describe(status: ExitStatus) -> String {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+
+        if let Some(signal) = status.signal() {
+            let core = if status.core_dumped() { ", core dumped" } else { "" };
+            return format!("worker crashed (signal {}{})", signal, core);
+        }
+    }
+
+    match status.code() {
+        Some(code) => format!("worker exited with status {}", code),
+        None => "worker exited abnormally".to_string(),
+    }
+}